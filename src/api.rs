@@ -0,0 +1,754 @@
+//! Control socket of the running daemon.
+//!
+//! A Unix stream socket, owned by root and the daemon's `default_group`
+//! with mode 0660, speaking newline‑delimited JSON: one [`Request`] per
+//! line, answered by one [`Response`] line.  Every message carries the
+//! protocol `version`; a request for another version is refused.
+//!
+//! ```text
+//! → {"version":1,"op":"status","service":"web"}
+//! ← {"version":1,"result":"service","service":{"name":"web","state":"running",…}}
+//! ```
+//!
+//! Requests are handed to the manager's event loop, which owns all service
+//! state and answers them there.
+//!
+//! A `subscribe` request turns the connection into an event stream: one
+//! `event` response per line until the client hangs up.
+
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, bounded};
+use log::{debug, warn};
+use nix::unistd::{Group, Uid, chown};
+use serde::{Deserialize, Serialize};
+
+use crate::ipc::Evt;
+use crate::service::ephemeral;
+use crate::service::logs::LogLine;
+
+/// Version of the request/response protocol.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The socket of a root daemon.
+#[cfg(target_os = "linux")]
+const SYSTEM_SOCKET: &str = "/run/kodegend/control.sock";
+#[cfg(not(target_os = "linux"))]
+const SYSTEM_SOCKET: &str = "/var/run/kodegend/control.sock";
+
+/// Parent of the per-user directory when `XDG_RUNTIME_DIR` is not set.  Not
+/// the temporary directory: `TMPDIR` may differ between CLI and daemon.
+const USER_SOCKET_BASE: &str = "/tmp";
+
+/// Longest request line accepted.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// How long a connection waits for the manager to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a quiet event stream checks whether its client hung up.
+const HANGUP_CHECK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    #[serde(flatten)]
+    pub op: Op,
+}
+
+/// What a client asks for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    /// Every service with its status.
+    List,
+    Status {
+        service: String,
+    },
+    Start {
+        service: String,
+    },
+    Stop {
+        service: String,
+    },
+    Restart {
+        service: String,
+    },
+    /// The newest `lines` captured output lines, only those with a `seq`
+    /// above `after` if given.
+    Logs {
+        service: String,
+        lines: usize,
+        #[serde(default)]
+        after: Option<u64>,
+    },
+    /// The newest `limit` events of the manager matching `filter`.
+    Events {
+        #[serde(flatten)]
+        filter: EventFilter,
+        limit: usize,
+    },
+    /// Stream the events matching `filter`, starting with up to `history`
+    /// recent ones.
+    Subscribe {
+        #[serde(flatten)]
+        filter: EventFilter,
+        #[serde(default)]
+        history: usize,
+    },
+    /// Create and start an instance of a `services_dir` template.
+    AddInstance {
+        service: String,
+    },
+    /// Re-read the configuration.
+    Reload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: Body,
+}
+
+/// The manager's answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Body {
+    Services { services: Vec<ServiceStatus> },
+    Service { service: ServiceStatus },
+    Logs { lines: Vec<LogLine> },
+    Events { events: Vec<StreamEvent> },
+    Event { event: StreamEvent },
+    Done { message: String },
+    Error { error: String },
+}
+
+impl Body {
+    pub fn done(message: impl Into<String>) -> Self {
+        Self::Done {
+            message: message.into(),
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Self::Error {
+            error: error.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    /// Lifecycle state: `stopped`, `starting`, `running`, `stopping`,
+    /// `restarting` or `failed`.
+    pub state: String,
+    pub pid: Option<u32>,
+    /// Seconds since the current process reported running.
+    pub uptime_s: Option<u64>,
+    /// Consecutive automatic restarts.
+    pub restarts: u32,
+    /// Outcome of the last health check, if the service has one.
+    pub healthy: Option<bool>,
+    /// Whether dependents may rely on the service.
+    pub ready: bool,
+}
+
+/// What the manager publishes to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StreamEvent {
+    State {
+        service: String,
+        state: String,
+        pid: Option<u32>,
+        ts: DateTime<Utc>,
+    },
+    Health {
+        service: String,
+        healthy: bool,
+        failures: u32,
+        reason: Option<String>,
+        ts: DateTime<Utc>,
+    },
+    LogRotate {
+        service: String,
+        ts: DateTime<Utc>,
+    },
+    Fatal {
+        service: String,
+        message: String,
+        ts: DateTime<Utc>,
+    },
+    /// The subscriber fell behind and `missed` events were dropped.
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl StreamEvent {
+    /// The published part of the bus, if `evt` belongs to it.
+    #[must_use]
+    pub fn from_evt(evt: &Evt) -> Option<Self> {
+        Some(match evt {
+            Evt::State {
+                service,
+                kind,
+                ts,
+                pid,
+            } => Self::State {
+                service: service.clone(),
+                state: (*kind).to_string(),
+                pid: *pid,
+                ts: *ts,
+            },
+            Evt::Health {
+                service,
+                healthy,
+                failures,
+                reason,
+                ts,
+            } => Self::Health {
+                service: service.clone(),
+                healthy: *healthy,
+                failures: *failures,
+                reason: reason.clone(),
+                ts: *ts,
+            },
+            Evt::LogRotate { service, ts } => Self::LogRotate {
+                service: service.clone(),
+                ts: *ts,
+            },
+            Evt::Fatal { service, msg, ts } => Self::Fatal {
+                service: service.clone(),
+                message: (*msg).to_string(),
+                ts: *ts,
+            },
+            _ => return None,
+        })
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> Option<EventKind> {
+        match self {
+            Self::State { .. } => Some(EventKind::State),
+            Self::Health { .. } => Some(EventKind::Health),
+            Self::LogRotate { .. } => Some(EventKind::LogRotate),
+            Self::Fatal { .. } => Some(EventKind::Fatal),
+            Self::Lagged { .. } => None,
+        }
+    }
+
+    #[must_use]
+    pub fn service(&self) -> Option<&str> {
+        match self {
+            Self::State { service, .. }
+            | Self::Health { service, .. }
+            | Self::LogRotate { service, .. }
+            | Self::Fatal { service, .. } => Some(service),
+            Self::Lagged { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    State,
+    Health,
+    LogRotate,
+    Fatal,
+}
+
+/// Which events a subscriber wants; empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub services: Vec<String>,
+    #[serde(default)]
+    pub kinds: Vec<EventKind>,
+}
+
+impl EventFilter {
+    /// Whether `event` passes; `Lagged` always does.
+    #[must_use]
+    pub fn matches(&self, event: &StreamEvent) -> bool {
+        let service = event.service().is_none_or(|service| {
+            self.services.is_empty() || self.services.iter().any(|s| s == service)
+        });
+        let kind = event
+            .kind()
+            .is_none_or(|kind| self.kinds.is_empty() || self.kinds.contains(&kind));
+        service && kind
+    }
+}
+
+/// What a connection hands to the manager.
+#[derive(Debug)]
+pub enum Call {
+    /// A request with the channel for its answer.
+    Request(Op, Sender<Body>),
+    /// A subscription; the manager answers with the subscriber's queue.
+    Subscribe {
+        filter: EventFilter,
+        history: usize,
+        reply: Sender<Receiver<StreamEvent>>,
+    },
+}
+
+/// Where the daemon listens: a system path for root (systemd's
+/// `RuntimeDirectory` on Linux), one in the private per‑user directory
+/// below `XDG_RUNTIME_DIR` or `/tmp` otherwise.
+#[must_use]
+pub fn socket_path() -> PathBuf {
+    if Uid::effective().is_root() {
+        return PathBuf::from(SYSTEM_SOCKET);
+    }
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| PathBuf::from(USER_SOCKET_BASE));
+    base.join(format!("kodegend-{}", Uid::effective()))
+        .join("control.sock")
+}
+
+/// A connection to the daemon's control socket.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    /// Send `op` and wait for the daemon's answer.
+    pub fn call(&mut self, op: Op) -> io::Result<Body> {
+        let mut out = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            op,
+        })?;
+        out.push('\n');
+        self.writer.write_all(out.as_bytes())?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "daemon closed the connection",
+            ));
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        if response.version != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "daemon speaks protocol version {}, this client {PROTOCOL_VERSION}",
+                    response.version
+                ),
+            ));
+        }
+        Ok(response.body)
+    }
+
+    /// Turn the connection into a stream of the events matching `filter`,
+    /// starting with up to `history` recent ones.
+    pub fn subscribe(
+        mut self,
+        filter: EventFilter,
+        history: usize,
+    ) -> io::Result<impl Iterator<Item = io::Result<StreamEvent>>> {
+        let mut out = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            op: Op::Subscribe { filter, history },
+        })?;
+        out.push('\n');
+        self.writer.write_all(out.as_bytes())?;
+        Ok(self.reader.lines().map(|line| {
+            let response: Response = serde_json::from_str(&line?)?;
+            match response.body {
+                Body::Event { event } => Ok(event),
+                Body::Error { error } => Err(io::Error::other(error)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected answer in event stream",
+                )),
+            }
+        }))
+    }
+}
+
+/// The bound socket; its file is removed on drop.
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Listen on `path`, readable and writable by `group`.  Requests arrive on
+/// the returned receiver.
+pub fn serve(path: &Path, group: Option<&str>) -> io::Result<(ControlSocket, Receiver<Call>)> {
+    match path.parent() {
+        // The per-user directory may sit in the shared `/tmp`, where anyone
+        // could have created it first.
+        Some(dir) if !Uid::effective().is_root() => {
+            let shared = dir.parent().unwrap_or(dir);
+            if ephemeral::private_base(shared)? != dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not the daemon's private directory", dir.display()),
+                ));
+            }
+        }
+        Some(dir) => DirBuilder::new().recursive(true).mode(0o755).create(dir)?,
+        None => {}
+    }
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another daemon", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let socket = ControlSocket {
+        path: path.to_path_buf(),
+    };
+    let gid = group.and_then(|name| {
+        let gid = match Group::from_name(name) {
+            Ok(Some(group)) => group.gid,
+            _ => {
+                warn!("Control socket: unknown group '{name}' – only its owner may connect");
+                return None;
+            }
+        };
+        match chown(path, None, Some(gid)) {
+            Ok(()) => Some(gid),
+            Err(e) => {
+                warn!("Control socket: cannot hand it to group '{name}': {e}");
+                warn!("Control socket: only its owner may connect");
+                None
+            }
+        }
+    });
+    let mode = if gid.is_some() { 0o660 } else { 0o600 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    let (tx, rx) = bounded::<Call>(16);
+    thread::Builder::new()
+        .name("control".into())
+        .spawn(move || accept(&listener, &tx))?;
+    Ok((socket, rx))
+}
+
+fn accept(listener: &UnixListener, calls: &Sender<Call>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Control socket: accept failed: {e}");
+                continue;
+            }
+        };
+        let calls = calls.clone();
+        let spawned = thread::Builder::new()
+            .name("control-conn".into())
+            .spawn(move || {
+                if let Err(e) = converse(stream, &calls) {
+                    debug!("Control connection closed: {e}");
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Control socket: cannot handle connection: {e}");
+        }
+    }
+}
+
+/// Answer requests on one connection until the client hangs up.
+fn converse(stream: UnixStream, calls: &Sender<Call>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = (&mut reader).take(MAX_REQUEST_BYTES).read_line(&mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') && read as u64 == MAX_REQUEST_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request too long",
+            ));
+        }
+        let body = match serde_json::from_str::<Request>(&line) {
+            Err(e) => Body::error(format!("invalid request: {e}")),
+            Ok(req) if req.version != PROTOCOL_VERSION => Body::error(format!(
+                "unsupported protocol version {} (daemon speaks {PROTOCOL_VERSION})",
+                req.version
+            )),
+            Ok(Request {
+                op: Op::Subscribe { filter, history },
+                ..
+            }) => return stream_events(&mut writer, filter, history, calls),
+            Ok(req) => dispatch(req.op, calls),
+        };
+        respond(&mut writer, body)?;
+    }
+}
+
+fn respond(writer: &mut UnixStream, body: Body) -> io::Result<()> {
+    let mut out = serde_json::to_string(&Response {
+        version: PROTOCOL_VERSION,
+        body,
+    })?;
+    out.push('\n');
+    writer.write_all(out.as_bytes())
+}
+
+/// Write events to the client until it or the manager goes away.
+fn stream_events(
+    writer: &mut UnixStream,
+    filter: EventFilter,
+    history: usize,
+    calls: &Sender<Call>,
+) -> io::Result<()> {
+    let (reply, answer) = bounded(1);
+    let subscribed = calls
+        .send(Call::Subscribe {
+            filter,
+            history,
+            reply,
+        })
+        .ok()
+        .and_then(|()| answer.recv_timeout(REPLY_TIMEOUT).ok());
+    let Some(events) = subscribed else {
+        return respond(writer, Body::error("daemon is shutting down"));
+    };
+    loop {
+        match events.recv_timeout(HANGUP_CHECK) {
+            Ok(event) => respond(writer, Body::Event { event })?,
+            Err(RecvTimeoutError::Timeout) if hung_up(writer) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Whether the peer of `stream` has closed its end.
+fn hung_up(stream: &UnixStream) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLRDHUP,
+        revents: 0,
+    };
+    // SAFETY: `pollfd` is a single valid entry.
+    let rc = unsafe { libc::poll(&raw mut pollfd, 1, 0) };
+    rc > 0 && pollfd.revents & (libc::POLLRDHUP | libc::POLLHUP | libc::POLLERR) != 0
+}
+
+fn dispatch(op: Op, calls: &Sender<Call>) -> Body {
+    let (reply, answer) = bounded(1);
+    if calls.send(Call::Request(op, reply)).is_err() {
+        return Body::error("daemon is shutting down");
+    }
+    answer
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| Body::error("no answer from the daemon"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::JoinHandle;
+
+    use super::*;
+
+    /// Run `converse` on one end of a socket pair and return the other end
+    /// with the calls the connection hands to the manager.
+    fn connection() -> (UnixStream, Receiver<Call>, JoinHandle<io::Result<()>>) {
+        let (client, server) = UnixStream::pair().expect("socket pair");
+        let (tx, calls) = bounded(1);
+        let handle = thread::spawn(move || converse(server, &tx));
+        (client, calls, handle)
+    }
+
+    fn read_response(client: &UnixStream) -> Response {
+        let mut line = String::new();
+        BufReader::new(client)
+            .read_line(&mut line)
+            .expect("read response");
+        serde_json::from_str(&line).expect("parse response")
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let ops = [
+            Op::List,
+            Op::Restart {
+                service: "web".into(),
+            },
+            Op::Logs {
+                service: "web".into(),
+                lines: 20,
+                after: Some(7),
+            },
+            Op::Events {
+                filter: EventFilter::default(),
+                limit: 5,
+            },
+            Op::Subscribe {
+                filter: EventFilter {
+                    services: vec!["web".into()],
+                    kinds: vec![EventKind::Health, EventKind::LogRotate],
+                },
+                history: 3,
+            },
+            Op::AddInstance {
+                service: "agent@a".into(),
+            },
+            Op::Reload,
+        ];
+        for op in ops {
+            let json = serde_json::to_string(&Request {
+                version: PROTOCOL_VERSION,
+                op: op.clone(),
+            })
+            .expect("serialize");
+            let back: Request = serde_json::from_str(&json).expect("deserialize");
+            assert_eq!(back.version, PROTOCOL_VERSION);
+            assert_eq!(back.op, op, "{json}");
+        }
+
+        let documented: Request =
+            serde_json::from_str(r#"{"version":1,"op":"status","service":"web"}"#)
+                .expect("deserialize");
+        assert_eq!(
+            documented.op,
+            Op::Status {
+                service: "web".into()
+            }
+        );
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let event = StreamEvent::Health {
+            service: "web".into(),
+            healthy: false,
+            failures: 2,
+            reason: Some("timeout".into()),
+            ts: Utc::now(),
+        };
+        let bodies = [
+            Body::Event {
+                event: event.clone(),
+            },
+            Body::Service {
+                service: ServiceStatus {
+                    name: "web".into(),
+                    state: "running".into(),
+                    pid: Some(42),
+                    uptime_s: Some(3),
+                    restarts: 1,
+                    healthy: None,
+                    ready: true,
+                },
+            },
+            Body::done("started web"),
+            Body::error("no service 'db'"),
+        ];
+        for body in bodies {
+            let json = serde_json::to_string(&Response {
+                version: PROTOCOL_VERSION,
+                body,
+            })
+            .expect("serialize");
+            let back: Response = serde_json::from_str(&json).expect("deserialize");
+            assert_eq!(back.version, PROTOCOL_VERSION);
+            match back.body {
+                Body::Event { event: back } => assert_eq!(back, event),
+                Body::Service { service } => {
+                    assert_eq!(service.name, "web");
+                    assert_eq!(service.pid, Some(42));
+                    assert!(service.ready);
+                }
+                Body::Done { message } => assert_eq!(message, "started web"),
+                Body::Error { error } => assert_eq!(error, "no service 'db'"),
+                other => panic!("unexpected body {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn passes_requests_to_the_manager() {
+        let (mut client, calls, _conn) = connection();
+        client
+            .write_all(b"{\"version\":1,\"op\":\"start\",\"service\":\"web\"}\n")
+            .expect("write request");
+        let Ok(Call::Request(op, reply)) = calls.recv_timeout(Duration::from_secs(5)) else {
+            panic!("no request reached the manager");
+        };
+        assert_eq!(
+            op,
+            Op::Start {
+                service: "web".into()
+            }
+        );
+        reply.send(Body::done("started web")).expect("reply");
+        let response = read_response(&client);
+        assert!(matches!(response.body, Body::Done { message } if message == "started web"));
+    }
+
+    #[test]
+    fn refuses_other_protocol_versions() {
+        let (mut client, calls, conn) = connection();
+        client
+            .write_all(b"{\"version\":99,\"op\":\"list\"}\n")
+            .expect("write request");
+        let response = read_response(&client);
+        assert_eq!(response.version, PROTOCOL_VERSION);
+        match response.body {
+            Body::Error { error } => assert!(error.contains("version 99"), "{error}"),
+            other => panic!("expected an error, got {other:?}"),
+        }
+        assert!(calls.try_recv().is_err());
+
+        drop(client);
+        assert!(conn.join().expect("connection thread").is_ok());
+    }
+
+    #[test]
+    fn rejects_overlong_requests() {
+        let (mut client, calls, conn) = connection();
+        let request = vec![b'x'; MAX_REQUEST_BYTES as usize + 1];
+        // The daemon hangs up mid-request, so the tail may not fit in.
+        client.write_all(&request).ok();
+        let err = conn
+            .join()
+            .expect("connection thread")
+            .expect_err("request accepted");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(calls.try_recv().is_err());
+    }
+}
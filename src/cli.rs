@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::api::EventKind;
 
 #[derive(Parser, Debug)]
 #[command(version, about = "kodegen service manager")]
@@ -32,4 +34,72 @@ pub enum Cmd {
     Stop,
     /// Restart the daemon service (Exit 0 = success, 1 = failed)
     Restart,
+    /// List the supervised services with their state, pid, uptime,
+    /// restarts and health
+    Services {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Control one service of the running daemon (Exit 0 = success, 1 = failed;
+    /// for `status`, 0 = running)
+    Service {
+        /// Service name
+        name: String,
+
+        /// What to do with it
+        action: ServiceAction,
+
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a service's captured output
+    Logs {
+        /// Service name
+        name: String,
+
+        /// Keep printing new output until interrupted
+        #[arg(long, short = 'f')]
+        follow: bool,
+
+        /// Number of lines to show
+        #[arg(long, short = 'n', default_value_t = 50)]
+        lines: usize,
+
+        /// Print one JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show recent state changes, health results, log rotations and fatal
+    /// errors of the running daemon
+    Events {
+        /// Keep printing new events until interrupted
+        #[arg(long, short = 'f')]
+        follow: bool,
+
+        /// Number of recent events to show
+        #[arg(long, short = 'n', default_value_t = 20)]
+        lines: usize,
+
+        /// Only events of this service (repeatable)
+        #[arg(long = "service", short = 's')]
+        services: Vec<String>,
+
+        /// Only events of this kind (repeatable)
+        #[arg(long = "kind", short = 'k', value_enum)]
+        kinds: Vec<EventKind>,
+
+        /// Print one JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Status,
 }
@@ -38,3 +38,13 @@ pub fn info(message: &str) {
     let _ = stdout.reset();
     log::info!("{message}");
 }
+
+/// Print a plain line to stdout, in `color` if given (table rows, not logged)
+pub fn row(message: &str, color: Option<Color>) {
+    let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+    if let Some(color) = color {
+        let _ = stdout.set_color(ColorSpec::new().set_fg(Some(color)));
+    }
+    let _ = writeln!(&mut stdout, "{message}");
+    let _ = stdout.reset();
+}
@@ -11,6 +11,10 @@ pub struct ServiceConfig {
     pub default_group: Option<String>,
     pub auto_restart: Option<bool>,
     pub services: Vec<ServiceDefinition>,
+    /// Instances of `services_dir` templates to run, e.g. `agent@project-a`
+    /// for the template `agent@.toml`.
+    #[serde(default)]
+    pub instances: Vec<String>,
     /// MCP Streamable HTTP transport binding (host:port)
     pub mcp_bind: Option<String>,
     /// Category HTTP servers (14 tool categories)
@@ -204,6 +208,7 @@ impl Default for ServiceConfig {
             default_group: Some("cyops".into()),
             auto_restart: Some(true),
             services: vec![],
+            instances: vec![],
             mcp_bind: Some("0.0.0.0:33399".into()),
             category_servers: ServiceConfig::default_category_servers(),
         }
@@ -211,14 +216,19 @@ impl Default for ServiceConfig {
 }
 
 /// On‑disk TOML description of a single service.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ServiceDefinition {
     pub name: String,
     pub description: Option<String>,
     pub command: String,
     pub working_dir: Option<String>,
+    /// Values may reference secrets as `${file:/path}` or `${env:NAME}`.
     #[serde(default)]
     pub env_vars: HashMap<String, String>,
+    /// dotenv files read before `env_vars`, which override them; they must
+    /// not be accessible by everyone.
+    #[serde(default)]
+    pub env_files: Vec<String>,
     #[serde(default)]
     pub auto_restart: bool,
     pub user: Option<String>,
@@ -233,18 +243,254 @@ pub struct ServiceDefinition {
     #[serde(default)]
     pub watch_dirs: Vec<String>,
     pub ephemeral_dir: Option<String>,
-    /// Service type (e.g., "autoconfig" for special handling)
+    /// Service type (e.g., "autoconfig" for special handling).  `oneshot`
+    /// services run to completion once, `scheduled` ones on `schedule`; for
+    /// both, exit status 0 counts as success.
     pub service_type: Option<String>,
+    /// When a `scheduled` service runs: a cron expression (`0 3 * * *`), a
+    /// shorthand such as `@daily`, or an interval such as `@every 15m`.
+    pub schedule: Option<String>,
     pub memfs: Option<MemoryFsConfig>,
+    /// Signal sent to the service's process group on stop (default SIGTERM).
+    pub stop_signal: Option<String>,
+    /// Seconds to wait after `stop_signal` before escalating to SIGKILL.
+    pub stop_timeout_s: Option<u64>,
+    /// Restart policy, backoff cap and crash‑loop limits.
+    #[serde(default)]
+    pub restart: Option<RestartConfig>,
+    /// cgroup v2 limits for the service's processes.
+    #[serde(default)]
+    pub resources: Option<ResourcesConfig>,
+    /// Namespace and seccomp confinement applied before exec.
+    #[serde(default)]
+    pub sandbox: Option<SandboxConfig>,
+    /// When the started service counts as running (default: on spawn).
+    #[serde(default)]
+    pub readiness: Option<ReadinessConfig>,
+    /// Sockets kodegend listens on, starting the service on demand.  The
+    /// service's `command` is exec'd and must be a single command.
+    #[serde(default)]
+    pub socket_activation: Option<SocketActivationConfig>,
+    /// Commands run around start and stop.
+    #[serde(default)]
+    pub hooks: Option<HooksConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ServiceDefinition {
+    /// Whether the service runs to completion rather than staying up.
+    #[must_use]
+    pub fn runs_to_completion(&self) -> bool {
+        matches!(self.service_type.as_deref(), Some("oneshot" | "scheduled"))
+    }
+
+    /// Whether the service is started by connections to its sockets.
+    #[must_use]
+    pub fn socket_activated(&self) -> bool {
+        self.socket_activation.is_some()
+    }
+}
+
+/// When a service that went down is brought back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestartConfig {
+    /// Defaults to `always` with `auto_restart = true`, otherwise to
+    /// `on-failure` or `never` following the daemon's `auto_restart`.
+    pub policy: Option<RestartPolicy>,
+    #[serde(default = "RestartConfig::default_max_delay_s")]
+    pub max_delay_s: u64,
+    /// More than `burst` restarts within `window_s` mark the service failed.
+    #[serde(default = "RestartConfig::default_burst")]
+    pub burst: u32,
+    #[serde(default = "RestartConfig::default_window_s")]
+    pub window_s: u64,
+    /// Uptime after which the attempt counter starts from zero again.
+    #[serde(default = "RestartConfig::default_reset_after_s")]
+    pub reset_after_s: u64,
+}
+
+impl RestartConfig {
+    fn default_max_delay_s() -> u64 {
+        300
+    }
+
+    fn default_burst() -> u32 {
+        5
+    }
+
+    fn default_window_s() -> u64 {
+        60
+    }
+
+    fn default_reset_after_s() -> u64 {
+        300
+    }
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            policy: None,
+            max_delay_s: Self::default_max_delay_s(),
+            burst: Self::default_burst(),
+            window_s: Self::default_window_s(),
+            reset_after_s: Self::default_reset_after_s(),
+        }
+    }
+}
+
+/// How a started service signals that it is ready.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadinessMode {
+    /// Ready as soon as the process is spawned.
+    #[default]
+    Exec,
+    /// `READY=1` on the `NOTIFY_SOCKET` (sd_notify).
+    Notify,
+    /// `host:port` accepts connections.
+    TcpPort,
+    /// A stdout line matches `pattern`.
+    LogLine,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadinessConfig {
+    #[serde(default)]
+    pub mode: ReadinessMode,
+    /// `tcp-port`: host to connect to (default 127.0.0.1).
+    pub host: Option<String>,
+    /// `tcp-port`: port to connect to.
+    pub port: Option<u16>,
+    /// `log-line`: regular expression a stdout line must match.
+    pub pattern: Option<String>,
+    /// Seconds to wait for readiness before the start counts as failed.
+    #[serde(default = "ReadinessConfig::default_timeout_s")]
+    pub timeout_s: u64,
+}
+
+impl ReadinessConfig {
+    fn default_timeout_s() -> u64 {
+        30
+    }
+}
+
+/// Hook commands per lifecycle transition, run in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub pre_start: Vec<HookConfig>,
+    pub post_start: Vec<HookConfig>,
+    pub pre_stop: Vec<HookConfig>,
+    pub post_stop: Vec<HookConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookConfig {
+    pub command: String,
+    /// Seconds before the hook is killed and counts as failed.
+    #[serde(default = "HookConfig::default_timeout_s")]
+    pub timeout_s: u64,
+    #[serde(default)]
+    pub on_failure: HookFailure,
+}
+
+impl HookConfig {
+    fn default_timeout_s() -> u64 {
+        60
+    }
+}
+
+/// What a failed start hook does; stop hooks only ever warn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailure {
+    /// The start fails.
+    #[default]
+    Abort,
+    /// Logged, and the start goes on.
+    Warn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketActivationConfig {
+    /// `host:port` for TCP or an absolute path for a Unix socket, passed to
+    /// the service as descriptors 3, 4, … in this order.
+    pub listen: Vec<String>,
+    /// Stop the service after this many seconds without a connection; it
+    /// starts again on the next one.  Linux only.
+    pub idle_timeout_s: Option<u64>,
+    /// Permissions of Unix socket files (default 0o666).
+    pub socket_mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MemoryFsConfig {
-    pub size_mb: u32, // clamped at 2048 elsewhere
+    pub size_mb: u32, // clamped to memfs::MAX_SIZE_MB (2048)
     pub mount_name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Per‑service cgroup v2 limits; unset fields stay unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourcesConfig {
+    /// `memory.max`, in megabytes.
+    pub memory_max_mb: Option<u64>,
+    /// `cpu.weight` (1–10000, kernel default 100).
+    pub cpu_weight: Option<u32>,
+    /// `cpu.max` as a percentage of one CPU (200 = two full cores).
+    pub cpu_quota_percent: Option<u32>,
+    /// `pids.max`.
+    pub pids_max: Option<u64>,
+    /// `io.weight` (1–10000, kernel default 100).
+    pub io_weight: Option<u32>,
+}
+
+impl ResourcesConfig {
+    /// Whether any limit is set, rather than accounting only.
+    #[must_use]
+    pub fn sets_limits(&self) -> bool {
+        self.memory_max_mb.is_some()
+            || self.cpu_weight.is_some()
+            || self.cpu_quota_percent.is_some()
+            || self.pids_max.is_some()
+            || self.io_weight.is_some()
+    }
+}
+
+/// Confinement of a service's processes; everything is off by default.
+///
+/// The namespace options need a root daemon; `seccomp_profile` alone works
+/// unprivileged.  Under the installed systemd unit they also need the
+/// namespaces, mount syscalls and CAP_SYS_ADMIN the installer only grants
+/// if a service had `sandbox` at install time: one added later through
+/// `services_dir` or a reload fails to start until the daemon is
+/// reinstalled.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Fresh, empty tmpfs on `/tmp`.
+    pub private_tmp: bool,
+    /// Remount every filesystem read‑only except `read_write_paths`.
+    pub read_only_root: bool,
+    /// Stay writable under `read_only_root`; `ephemeral_dir` and the memfs
+    /// mount are added automatically.
+    pub read_write_paths: Vec<String>,
+    /// Empty network namespace with only loopback.
+    pub no_network: bool,
+    /// Own PID namespace: the service sees only its own processes.
+    pub private_pid: bool,
+    /// seccomp filter: `default` or `no-network`.
+    pub seccomp_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HealthCheckConfig {
     pub check_type: String, // http | tcp | script
     pub target: String,
@@ -256,7 +502,7 @@ pub struct HealthCheckConfig {
     pub on_failure: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LogRotationConfig {
     pub max_size_mb: u64,
     pub max_files: u32,
@@ -128,6 +128,16 @@ fn convert_to_service_definition(
             "kodegen-autoconfig" => "autoconfig".to_string(),
             _ => "service".to_string(),
         }),
+        schedule: None,
         memfs: None,
+        stop_signal: None,
+        stop_timeout_s: None,
+        restart: None,
+        resources: None,
+        sandbox: None,
+        readiness: None,
+        socket_activation: None,
+        env_files: vec![],
+        hooks: None,
     })
 }
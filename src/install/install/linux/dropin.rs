@@ -28,6 +28,22 @@ pub(super) fn create_dropin_config(config: &SystemdConfig) -> Result<(), Install
         InstallerError::System(format!("Failed to create drop-in directory: {}", e))
     })?;
 
+    // Service `sandbox` blocks mount a private view of the filesystem and
+    // create namespaces, which takes CAP_SYS_ADMIN and the mount syscalls.
+    // That widens the daemon's own confinement, so only when asked for;
+    // without it `memfs` uses plain directories instead of tmpfs mounts.
+    let sandbox_content = if config.sandboxing {
+        "# Widened for service `sandbox` blocks: mounts and namespaces\n\
+         SystemCallFilter=@mount\n"
+    } else {
+        ""
+    };
+    let sandbox_capability = if config.sandboxing {
+        " CAP_SYS_ADMIN"
+    } else {
+        ""
+    };
+
     // Create override configuration for advanced features
     let override_content = format!(
         r#"[Service]
@@ -35,14 +51,17 @@ pub(super) fn create_dropin_config(config: &SystemdConfig) -> Result<(), Install
 MemoryMax=1G
 CPUQuota=200%
 TasksMax=1024
+# Let the daemon manage per-service sub-cgroups (service `resources`)
+Delegate=cpu memory pids io
 
 # Additional security
 SystemCallFilter=@system-service
-SystemCallErrorNumber=EPERM
+{}SystemCallErrorNumber=EPERM
 SystemCallArchitectures=native
 
 # Capability restrictions
-CapabilityBoundingSet=CAP_NET_BIND_SERVICE CAP_SETUID CAP_SETGID
+# CAP_CHOWN hands ephemeral_dir, memfs and notify sockets to service users
+CapabilityBoundingSet=CAP_NET_BIND_SERVICE CAP_SETUID CAP_SETGID CAP_CHOWN{}
 AmbientCapabilities=CAP_NET_BIND_SERVICE
 
 # Process management
@@ -53,6 +72,8 @@ Nice=-5
 X-Kodegen-Service=true
 X-Kodegen-Version={}
 "#,
+        sandbox_content,
+        sandbox_capability,
         env!("CARGO_PKG_VERSION")
     );
 
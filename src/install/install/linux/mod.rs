@@ -69,6 +69,8 @@ impl PlatformExecutor {
             wants_network: b.wants_network,
             user: None, // Run as root for system service, or current user for user service
             group: None,
+            sandboxing: b.services.iter().any(|s| s.sandbox.is_some()),
+            resource_limits: b.services.iter().any(|s| s.resources.is_some()),
         };
 
         // Generate and install systemd unit file
@@ -24,6 +24,10 @@ pub(super) struct SystemdConfig<'a> {
     pub wants_network: bool,
     pub user: Option<&'a str>,
     pub group: Option<&'a str>,
+    /// Whether an installed service uses `sandbox`.
+    pub sandboxing: bool,
+    /// Whether an installed service sets `resources`.
+    pub resource_limits: bool,
 }
 
 /// Create systemd unit file with comprehensive configuration in specified directory
@@ -120,16 +124,28 @@ fn generate_unit_content(config: &SystemdConfig) -> Result<String, InstallerErro
     content.push_str("ProtectSystem=strict\n");
     content.push_str("ProtectHome=true\n");
     content.push_str("ProtectKernelTunables=true\n");
-    content.push_str("ProtectControlGroups=true\n");
+    if !config.resource_limits {
+        // Otherwise the daemon writes its delegated cgroup subtree
+        // (`Delegate=` in the drop-in) for service `resources`.
+        content.push_str("ProtectControlGroups=true\n");
+    }
     content.push_str("RestrictSUIDSGID=true\n");
     content.push_str("RestrictRealtime=true\n");
-    content.push_str("RestrictNamespaces=true\n");
+    if config.sandboxing {
+        // Only the namespaces service `sandbox` blocks are built from
+        content.push_str("RestrictNamespaces=mnt net pid\n");
+    } else {
+        content.push_str("RestrictNamespaces=true\n");
+    }
     content.push_str("LockPersonality=true\n");
     content.push_str("MemoryDenyWriteExecute=true\n");
 
     // Allow specific directories for daemon operation
     content.push_str("ReadWritePaths=/var/log /var/lib /tmp\n");
     content.push_str("ReadOnlyPaths=/etc\n");
+    // /run/kodegend: control socket, notify sockets and memfs mounts
+    content.push_str("RuntimeDirectory=kodegend\n");
+    content.push_str("RuntimeDirectoryMode=0755\n");
 
     // Resource limits
     content.push_str("LimitNOFILE=65536\n");
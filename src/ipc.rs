@@ -1,4 +1,10 @@
+use std::fmt;
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+
+use crate::service::logs::LogLine;
 
 /// Commands sent *to* a worker thread.
 #[derive(Debug)]
@@ -7,8 +13,12 @@ pub enum Cmd {
     Stop,
     Restart,
     Shutdown,      // worker should exit
-    TickHealth,    // periodic health probe
     TickLogRotate, // periodic rotation
+    /// Reply with the newest `lines` captured output lines.
+    TailLogs {
+        lines: usize,
+        reply: Sender<Vec<LogLine>>,
+    },
 }
 
 /// Events emitted *from* workers back to the manager.
@@ -23,15 +33,100 @@ pub enum Evt {
     Health {
         service: String,
         healthy: bool,
+        /// Consecutive failed probes (0 when healthy).
+        failures: u32,
+        /// Why the last probe failed.
+        reason: Option<String>,
         ts: DateTime<Utc>,
     },
     LogRotate {
         service: String,
         ts: DateTime<Utc>,
     },
+    /// A supervised process has exited; `exit` is `None` if it could not
+    /// be reaped.  `expected` is true when the worker stopped it.
+    Exit {
+        service: String,
+        pid: u32,
+        exit: Option<ExitInfo>,
+        runtime: Duration,
+        expected: bool,
+        ts: DateTime<Utc>,
+    },
+    /// Periodic cgroup accounting of a service with `resources`.
+    Resources {
+        service: String,
+        memory_bytes: u64,
+        /// Total CPU time consumed by this run.
+        cpu_usec: u64,
+        ts: DateTime<Utc>,
+    },
+    /// The OOM killer struck inside the service's cgroup; `kills` is the
+    /// total for the current run.
+    OomKill {
+        service: String,
+        kills: u64,
+        ts: DateTime<Utc>,
+    },
+    /// A `scheduled` service's next run is due at `next_run`.
+    Scheduled {
+        service: String,
+        next_run: DateTime<Utc>,
+        ts: DateTime<Utc>,
+    },
+    /// A socket‑activated service waits for connections on `listen`.
+    Listening {
+        service: String,
+        listen: Vec<String>,
+        ts: DateTime<Utc>,
+    },
+    /// Outcome of one `on_failure` action.
+    FailureAction {
+        service: String,
+        action: String,
+        ok: bool,
+        detail: Option<String>,
+        ts: DateTime<Utc>,
+    },
     Fatal {
         service: String,
         msg: &'static str,
         ts: DateTime<Utc>,
     },
 }
+
+/// How a supervised process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitInfo {
+    /// Exit code, if the process exited normally.
+    pub code: Option<i32>,
+    /// Terminating signal, if the process was killed.
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+}
+
+impl ExitInfo {
+    /// Exited on its own with status 0.
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl fmt::Display for ExitInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code {code}"),
+            (None, Some(sig)) => {
+                let name =
+                    nix::sys::signal::Signal::try_from(sig).map_or("unknown", |s| s.as_str());
+                write!(f, "signal {sig} ({name})")?;
+                if self.core_dumped {
+                    f.write_str(", core dumped")?;
+                }
+                Ok(())
+            }
+            (None, None) => f.write_str("unknown status"),
+        }
+    }
+}
@@ -1,27 +1,47 @@
+use log::warn;
+
 use crate::state_machine::{Action, Event, State, Transition};
 
 /// Thin, inlineable helper that owns the state enum and
 /// returns the side‑effect requested by the transition table.
 #[derive(Copy, Clone)]
 pub struct Lifecycle {
+    name: &'static str,
     state: State,
 }
 impl Default for Lifecycle {
     fn default() -> Self {
+        Self::new("lifecycle")
+    }
+}
+impl Lifecycle {
+    /// A stopped lifecycle; `name` identifies it in log lines.
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
         Self {
+            name,
             state: State::Stopped,
         }
     }
-}
-impl Lifecycle {
-    /// Feed an `Event`, get back an `Action`.
-    #[inline(always)]
+
+    /// Feed an `Event`, get back an `Action`.  Impossible transitions are
+    /// logged and leave the state untouched.
+    #[inline]
     pub fn step(&mut self, e: Event) -> Action {
-        let (next, act) = Transition::next(self.state, e);
+        let Some((next, act)) = Transition::try_next(self.state, e) else {
+            warn!("{}: ignoring {e:?} while {:?}", self.name, self.state);
+            return Action::Noop;
+        };
         self.state = next;
         act
     }
 
+    #[inline(always)]
+    #[must_use]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
     #[inline(always)]
     #[must_use]
     pub fn is_running(&self) -> bool {
@@ -1,3 +1,4 @@
+mod api;
 mod cli;
 mod config;
 mod control;
@@ -10,11 +11,19 @@ mod state_machine;
 
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use chrono::SecondsFormat;
 use clap::Parser;
+use kodegend::cli_output;
 use log::{error, info};
 use manager::ServiceManager;
+use termcolor::Color;
+
+/// How often `logs --follow` asks for new output.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
 
 fn main() {
     // Initialize logger with custom format for daemon
@@ -28,7 +37,7 @@ fn main() {
                 record.level(),
                 record.file().unwrap_or("unknown"),
                 record.line().unwrap_or(0),
-                record.args()
+                service::secrets::redact(&record.args().to_string())
             )
         })
         .filter_level(log::LevelFilter::Info)
@@ -65,6 +74,21 @@ async fn real_main() -> Result<()> {
         cli::Cmd::Start => handle_start(),
         cli::Cmd::Stop => handle_stop(),
         cli::Cmd::Restart => handle_restart(),
+        cli::Cmd::Services { json } => handle_services(json),
+        cli::Cmd::Service { name, action, json } => handle_service(&name, action, json),
+        cli::Cmd::Logs {
+            name,
+            follow,
+            lines,
+            json,
+        } => handle_logs(&name, follow, lines, json),
+        cli::Cmd::Events {
+            follow,
+            lines,
+            services,
+            kinds,
+            json,
+        } => handle_events(api::EventFilter { services, kinds }, follow, lines, json),
     }
 }
 
@@ -142,15 +166,15 @@ async fn run_daemon(
 
     info!("Using config from: {}", cfg_path.display());
 
-    manager::install_signal_handlers()?;
-    let mut mgr = ServiceManager::new(&cfg)?;
+    let signals = manager::install_signal_handlers()?;
+    let mut mgr = ServiceManager::new(&cfg)?.with_config_path(cfg_path.clone());
 
     // Start category HTTP servers
     mgr.start_http_servers(&cfg).await?;
 
     daemon::systemd_ready(); // tell systemd we are ready
     info!("kodegen daemon started (pid {})", std::process::id());
-    mgr.run().await?;
+    mgr.run(signals).await?;
     info!("kodegen daemon exiting");
     Ok(())
 }
@@ -214,3 +238,274 @@ fn handle_restart() -> Result<()> {
         }
     }
 }
+
+/// Handle services command - list every service of the running daemon
+fn handle_services(json: bool) -> Result<()> {
+    let mut client = connect(json);
+    let api::Body::Services { services } = call(&mut client, api::Op::List, json) else {
+        fail("Unexpected answer from kodegend", json);
+    };
+    if json {
+        print_json(&services);
+    } else {
+        print_services(&services);
+    }
+    Ok(())
+}
+
+/// Handle service command - start, stop, restart or query one service
+fn handle_service(name: &str, action: cli::ServiceAction, json: bool) -> Result<()> {
+    let mut client = connect(json);
+    let service = name.to_string();
+    let op = match action {
+        cli::ServiceAction::Start => api::Op::Start { service },
+        cli::ServiceAction::Stop => api::Op::Stop { service },
+        cli::ServiceAction::Restart => api::Op::Restart { service },
+        cli::ServiceAction::Status => api::Op::Status { service },
+    };
+    match call(&mut client, op, json) {
+        api::Body::Service { service } => {
+            if json {
+                print_json(&service);
+            } else {
+                print_services(std::slice::from_ref(&service));
+            }
+            if service.state != "running" {
+                std::process::exit(1);
+            }
+        }
+        api::Body::Done { message } if !json => cli_output::success(&message),
+        body => print_json(&body),
+    }
+    Ok(())
+}
+
+/// Handle logs command - print a service's captured output
+fn handle_logs(name: &str, follow: bool, lines: usize, json: bool) -> Result<()> {
+    let mut client = connect(json);
+    // With nothing to show, the newest line only marks where following
+    // picks up.
+    let mut show = lines > 0;
+    let mut lines = lines.max(1);
+    let mut after = None;
+    loop {
+        let op = api::Op::Logs {
+            service: name.to_string(),
+            lines,
+            after,
+        };
+        let api::Body::Logs { lines: batch } = call(&mut client, op, json) else {
+            fail("Unexpected answer from kodegend", json);
+        };
+        if let (Some(cursor), Some(first)) = (after, batch.first())
+            && first.seq > cursor + 1
+        {
+            cli_output::warning(&format!(
+                "{} lines skipped – kodegend keeps only the newest {}",
+                first.seq - cursor - 1,
+                service::logs::TAIL_CAPACITY
+            ));
+        }
+        for line in batch.iter().filter(|_| show) {
+            if json {
+                print_json(line);
+            } else {
+                println!("{line}");
+            }
+        }
+        if !follow {
+            return Ok(());
+        }
+        show = true;
+        after = batch.last().map(|line| line.seq).or(after).or(Some(0));
+        lines = service::logs::TAIL_CAPACITY;
+        thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+/// Handle events command - print recent and, with `--follow`, live events
+fn handle_events(filter: api::EventFilter, follow: bool, lines: usize, json: bool) -> Result<()> {
+    let mut client = connect(json);
+    if !follow {
+        let op = api::Op::Events {
+            filter,
+            limit: lines,
+        };
+        let api::Body::Events { events } = call(&mut client, op, json) else {
+            fail("Unexpected answer from kodegend", json);
+        };
+        for event in &events {
+            print_event(event, json);
+        }
+        return Ok(());
+    }
+    let events = client
+        .subscribe(filter, lines)
+        .unwrap_or_else(|e| fail(&format!("Control socket: {e}"), json));
+    for event in events {
+        match event {
+            Ok(event) => print_event(&event, json),
+            Err(e) => fail(&format!("Event stream: {e}"), json),
+        }
+    }
+    if !json {
+        cli_output::warning("kodegend closed the event stream");
+    }
+    Ok(())
+}
+
+/// Connect to the daemon's control socket, or exit with an error
+fn connect(json: bool) -> api::Client {
+    // The terminal output is all the user needs; the log lines `cli_output`
+    // also emits would only repeat it.
+    log::set_max_level(log::LevelFilter::Off);
+    let path = api::socket_path();
+    match api::Client::connect(&path) {
+        Ok(client) => client,
+        Err(e) => fail(
+            &format!("Cannot reach kodegend at {}: {e}", path.display()),
+            json,
+        ),
+    }
+}
+
+/// Send one request; an error answer ends the command
+fn call(client: &mut api::Client, op: api::Op, json: bool) -> api::Body {
+    match client.call(op) {
+        Ok(api::Body::Error { error }) => fail(&error, json),
+        Ok(body) => body,
+        Err(e) => fail(&format!("Control socket: {e}"), json),
+    }
+}
+
+/// Report `message` and exit 1
+fn fail(message: &str, json: bool) -> ! {
+    if json {
+        print_json(&api::Body::error(message));
+    } else {
+        cli_output::error(message);
+    }
+    std::process::exit(1);
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(text) => println!("{text}"),
+        Err(e) => cli_output::error(&format!("Cannot encode JSON: {e}")),
+    }
+}
+
+fn print_services(services: &[api::ServiceStatus]) {
+    cli_output::row(
+        &format!(
+            "{:<24} {:<10} {:>8} {:>10} {:>8}  HEALTH",
+            "NAME", "STATE", "PID", "UPTIME", "RESTARTS"
+        ),
+        None,
+    );
+    for service in services {
+        let health = match service.healthy {
+            Some(true) => "healthy",
+            Some(false) => "unhealthy",
+            None => "-",
+        };
+        let color = state_color(&service.state, service.healthy);
+        cli_output::row(
+            &format!(
+                "{:<24} {:<10} {:>8} {:>10} {:>8}  {health}",
+                service.name,
+                service.state,
+                service.pid.map_or("-".to_string(), |pid| pid.to_string()),
+                service.uptime_s.map_or("-".to_string(), format_uptime),
+                service.restarts,
+            ),
+            color,
+        );
+    }
+}
+
+fn print_event(event: &api::StreamEvent, json: bool) {
+    use api::StreamEvent;
+
+    if json {
+        print_json(event);
+        return;
+    }
+    let stamp =
+        |ts: &chrono::DateTime<chrono::Utc>| ts.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let (line, color) = match event {
+        StreamEvent::State {
+            service,
+            state,
+            pid,
+            ts,
+        } => {
+            let pid = pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default();
+            (
+                format!("{} {service} → {state}{pid}", stamp(ts)),
+                state_color(state, None),
+            )
+        }
+        StreamEvent::Health {
+            service,
+            healthy: true,
+            ts,
+            ..
+        } => (
+            format!("{} {service} healthy", stamp(ts)),
+            Some(Color::Green),
+        ),
+        StreamEvent::Health {
+            service,
+            failures,
+            reason,
+            ts,
+            ..
+        } => (
+            format!(
+                "{} {service} unhealthy after {failures} failure(s): {}",
+                stamp(ts),
+                reason.as_deref().unwrap_or("unknown reason")
+            ),
+            Some(Color::Red),
+        ),
+        StreamEvent::LogRotate { service, ts } => {
+            (format!("{} {service} rotated logs", stamp(ts)), None)
+        }
+        StreamEvent::Fatal {
+            service,
+            message,
+            ts,
+        } => (
+            format!("{} {service} FATAL: {message}", stamp(ts)),
+            Some(Color::Red),
+        ),
+        StreamEvent::Lagged { missed } => (
+            format!("… {missed} event(s) missed – output too slow"),
+            Some(Color::Yellow),
+        ),
+    };
+    cli_output::row(&line, color);
+}
+
+/// Green when running (red if unhealthy), red when failed, yellow while
+/// changing state
+fn state_color(state: &str, healthy: Option<bool>) -> Option<Color> {
+    match (state, healthy) {
+        ("failed", _) | ("running", Some(false)) => Some(Color::Red),
+        ("running", _) => Some(Color::Green),
+        ("stopped", _) => None,
+        _ => Some(Color::Yellow),
+    }
+}
+
+/// `3d 4h`, `4h 12m`, `12m 5s` or `5s`
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{mins}m {}s", secs % 60),
+        (0, _, _) => format!("{hours}h {mins}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
@@ -1,32 +1,89 @@
-use std::collections::HashMap;
+mod deps;
+mod events;
+mod jobs;
+mod on_failure;
+mod reload;
+mod requests;
+mod restart;
+mod signals;
+mod templates;
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use crossbeam_channel::{Receiver, Sender, bounded, select, tick};
-use log::{error, info};
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender, bounded, never, select, tick};
+use log::{debug, error, info, warn};
+use nix::sys::signal::Signal;
+use tokio::runtime::Handle;
 
-use crate::config::ServiceConfig;
+use crate::api;
+use crate::config::{ServiceConfig, ServiceDefinition};
 use crate::ipc::{Cmd, Evt};
 use crate::lifecycle::Lifecycle;
-use crate::state_machine::{Action, Event};
+use crate::service::WorkerDefaults;
+use crate::service::activation;
+use crate::service::process::DEFAULT_STOP_TIMEOUT;
+use crate::state_machine::{Action, Event, State};
+use deps::DependencyGraph;
+use events::EventHub;
+pub use jobs::{JobStatus, LastRun};
+use on_failure::{FailureAction, FailureContext};
+use restart::{Decision, RestartTracker};
+pub use signals::install_signal_handlers;
+use templates::Templates;
 use crate::service::embedded_servers::{EmbeddedServer, start_all_servers, shutdown_all_servers};
 
 /// Global event bus size – small fixed size → zero heap growth.
 const BUS_BOUND: usize = 128;
 
-/// Restart state for a service
-#[derive(Debug)]
-struct RestartState {
-    stop_time: Instant,
-    attempts: u32,
-}
+/// Slack on top of a service's stop timeout during ordered shutdown.
+const SHUTDOWN_MARGIN: Duration = Duration::from_secs(5);
 
 /// Top‑level in‑process manager supervising *all* workers.
 pub struct ServiceManager {
     bus_tx: Sender<Evt>,
     bus_rx: Receiver<Evt>,
     workers: HashMap<String, Sender<Cmd>>,
-    pending_restarts: HashMap<String, RestartState>,
+    definitions: HashMap<String, ServiceDefinition>,
+    graph: DependencyGraph,
+    /// Services waiting for their dependencies before being started, in
+    /// start order.
+    awaiting: Vec<String>,
+    /// Services with a live process.
+    running: HashSet<String>,
+    /// Services dependents may rely on: running, and healthy if they have a
+    /// health check.
+    ready: HashSet<String>,
+    failure_actions: HashMap<String, Vec<FailureAction>>,
+    /// Services deliberately stopped by the manager, keyed to the service
+    /// whose recovery releases them.  Their stop events are not crashes.
+    held: HashMap<String, String>,
+    restarts: HashMap<String, RestartTracker>,
+    /// Services whose last unexpected exit had status 0.
+    clean_exits: HashSet<String>,
+    /// Last lifecycle state each worker reported.
+    states: HashMap<String, State>,
+    /// Pid of each running service and when it reported running.
+    processes: HashMap<String, (u32, Instant)>,
+    /// Outcome of each running service's last health check.
+    health: HashMap<String, bool>,
+    events: EventHub,
+    /// Changed services whose new worker starts once the old one's process
+    /// is gone, with the deadline after which it starts anyway.
+    replacing: HashMap<String, Instant>,
+    /// Where `reload` reads the configuration from.
+    config_path: Option<PathBuf>,
+    /// Run history of `oneshot` and `scheduled` services.
+    jobs: HashMap<String, JobStatus>,
+    /// Templates in `services_dir`, for instances added at runtime.
+    templates: Templates,
+    /// Instances added at runtime; they are not in the configuration, but
+    /// survive a reload.
+    added_instances: HashSet<String>,
+    defaults: WorkerDefaults,
+    auto_restart: bool,
     lifecycle: Lifecycle,
     embedded_servers: Option<Vec<EmbeddedServer>>,
 }
@@ -35,75 +92,158 @@ impl ServiceManager {
     /// Load config, spawn workers, and return the fully‑primed manager.
     pub fn new(cfg: &ServiceConfig) -> Result<Self> {
         let (bus_tx, bus_rx) = bounded::<Evt>(BUS_BOUND);
-        let mut workers = HashMap::new();
+        let defaults = WorkerDefaults::from(cfg);
+
+        // Gather every definition first so the dependency graph is validated
+        // before a single worker is spawned.
+        let Loaded {
+            definitions,
+            templates,
+            problems,
+        } = load_definitions(cfg);
+        for problem in problems {
+            error!("Skipping {problem}");
+        }
+        let graph =
+            DependencyGraph::build(definitions.values()).context("Invalid service dependencies")?;
 
-        // Load services from config file
-        for def in cfg.services.clone() {
-            match crate::service::spawn(def.clone(), bus_tx.clone()) {
+        let mut workers = HashMap::new();
+        for name in graph.start_order() {
+            match crate::service::spawn(definitions[name].clone(), bus_tx.clone(), &defaults) {
                 Ok(tx) => {
-                    workers.insert(def.name.clone(), tx);
+                    workers.insert(name.clone(), tx);
                 }
                 Err(e) => {
-                    error!("Failed to spawn service '{}': {}", def.name, e);
+                    error!("Failed to spawn service '{name}': {e}");
                     // Continue with other services - graceful degradation
                 }
             }
         }
 
-        // Load services from services directory
-        if let Some(services_dir) = &cfg.services_dir
-            && let Ok(entries) = std::fs::read_dir(services_dir)
-        {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("toml") {
-                    match std::fs::read_to_string(&path) {
-                        Ok(content) => {
-                            match toml::from_str::<crate::config::ServiceDefinition>(&content) {
-                                Ok(def) => {
-                                    match crate::service::spawn(def.clone(), bus_tx.clone()) {
-                                        Ok(tx) => {
-                                            info!(
-                                                "Loaded service '{}' from {}",
-                                                def.name,
-                                                path.display()
-                                            );
-                                            workers.insert(def.name.clone(), tx);
-                                        }
-                                        Err(e) => {
-                                            error!(
-                                                "Failed to spawn service '{}' from {}: {}",
-                                                def.name,
-                                                path.display(),
-                                                e
-                                            );
-                                            // Continue loading other services
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to parse service file {}: {}", path.display(), e)
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to read service file {}: {}", path.display(), e);
-                        }
-                    }
-                }
-            }
-        }
+        let failure_actions = definitions
+            .values()
+            .filter_map(|def| {
+                let hc = def.health_check.as_ref()?;
+                Some((
+                    def.name.clone(),
+                    on_failure::parse_actions(&def.name, &hc.on_failure),
+                ))
+            })
+            .collect();
+
+        let auto_restart = cfg.auto_restart.unwrap_or(true);
+        let restarts = definitions
+            .values()
+            .map(|def| (def.name.clone(), RestartTracker::new(def, auto_restart)))
+            .collect();
 
         Ok(Self {
             bus_tx,
             bus_rx,
             workers,
-            pending_restarts: HashMap::new(),
-            lifecycle: Lifecycle::default(),
+            definitions,
+            graph,
+            awaiting: Vec::new(),
+            running: HashSet::new(),
+            ready: HashSet::new(),
+            failure_actions,
+            held: HashMap::new(),
+            restarts,
+            clean_exits: HashSet::new(),
+            states: HashMap::new(),
+            processes: HashMap::new(),
+            health: HashMap::new(),
+            events: EventHub::default(),
+            replacing: HashMap::new(),
+            config_path: None,
+            jobs: HashMap::new(),
+            templates,
+            added_instances: HashSet::new(),
+            defaults,
+            auto_restart,
+            lifecycle: Lifecycle::new("manager"),
             embedded_servers: None,
         })
     }
 
+    /// Reload the configuration from `path` on SIGHUP or a control socket
+    /// `reload` request.
+    #[must_use]
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Current lifecycle state of `service`; a service whose restart policy
+    /// gave up reports `Failed` even after its worker stopped it.
+    #[must_use]
+    pub fn service_state(&self, service: &str) -> Option<State> {
+        if self
+            .restarts
+            .get(service)
+            .is_some_and(RestartTracker::is_failed)
+        {
+            return Some(State::Failed);
+        }
+        self.states.get(service).copied()
+    }
+
+    /// Last and next run of a `oneshot` or `scheduled` service.
+    #[allow(dead_code)] // Public API - exported in lib.rs, queried by external consumers
+    #[must_use]
+    pub fn job_status(&self, service: &str) -> Option<&JobStatus> {
+        self.jobs.get(service)
+    }
+
+    /// Create instance `name` (`<template>@<instance>`) of a `services_dir`
+    /// template at runtime and start it once its dependencies are ready.
+    pub fn add_instance(&mut self, name: &str) -> Result<()> {
+        if self.definitions.contains_key(name) {
+            anyhow::bail!("service '{name}' already exists");
+        }
+        let def = self.templates.instantiate(name)?;
+        if let Some(sa) = &def.socket_activation {
+            activation::check_supported(sa)?;
+        }
+        let graph = DependencyGraph::build(self.definitions.values().chain([&def]))
+            .with_context(|| format!("Invalid dependencies of '{name}'"))?;
+        let tx = crate::service::spawn(def.clone(), self.bus_tx.clone(), &self.defaults)
+            .with_context(|| format!("Failed to spawn service '{name}'"))?;
+
+        info!("Added instance '{name}'");
+        if let Some(hc) = &def.health_check {
+            self.failure_actions.insert(
+                name.to_string(),
+                on_failure::parse_actions(name, &hc.on_failure),
+            );
+        }
+        self.restarts.insert(
+            name.to_string(),
+            RestartTracker::new(&def, self.auto_restart),
+        );
+        self.workers.insert(name.to_string(), tx);
+        self.definitions.insert(name.to_string(), def);
+        self.added_instances.insert(name.to_string());
+        self.graph = graph;
+        // Before `run` the initial start-up pass picks it up.
+        if self.lifecycle.is_running() {
+            self.awaiting.push(name.to_string());
+            let graph = &self.graph;
+            self.awaiting.sort_by_key(|name| graph.rank(name));
+            self.start_ready();
+        }
+        Ok(())
+    }
+
+    /// Receive the state changes, health results, log rotations and fatal
+    /// errors matching `filter`.  A receiver that falls behind misses events
+    /// (see [`crate::api::StreamEvent::Lagged`]) rather than slowing the
+    /// manager down.
+    #[allow(dead_code)] // Public API - exported in lib.rs, called by external consumers
+    pub fn subscribe(&mut self, filter: api::EventFilter) -> Receiver<api::StreamEvent> {
+        self.events.subscribe(filter, 0)
+    }
+
     /// Start category HTTP servers as embedded in-process servers
     pub async fn start_http_servers(&mut self, cfg: &ServiceConfig) -> Result<()> {
         let configs = cfg.category_servers.clone();
@@ -125,72 +265,103 @@ impl ServiceManager {
         Ok(())
     }
 
-    /// Central event‑loop.  Runs until SIGINT / SIGTERM.
-    pub async fn run(mut self) -> Result<()> {
+    /// Central event‑loop.  Runs until SIGINT / SIGTERM arrives on
+    /// `signals` (see [`install_signal_handlers`]).
+    ///
+    /// The loop blocks, so it runs on the blocking pool rather than on a
+    /// runtime worker; the embedded servers it stops or starts keep running
+    /// on the calling runtime.
+    pub async fn run(self, signals: Receiver<Signal>) -> Result<()> {
+        let runtime = Handle::current();
+        tokio::task::spawn_blocking(move || self.supervise(&runtime, signals))
+            .await
+            .context("Manager loop panicked")?
+    }
+
+    fn supervise(mut self, runtime: &Handle, mut signals: Receiver<Signal>) -> Result<()> {
+        // Services still run without the socket, they just cannot be
+        // managed from the CLI.  The socket is removed again when `_control`
+        // goes out of scope.
+        let socket = api::socket_path();
+        let group = self.defaults.default_group.as_deref();
+        let (_control, requests) = match api::serve(&socket, group) {
+            Ok((control, requests)) => (Some(control), requests),
+            Err(e) => {
+                warn!("Control socket {} unavailable: {e}", socket.display());
+                (None, never())
+            }
+        };
+
         // Process lifecycle start event
         let action = self.lifecycle.step(Event::CmdStart);
         if action == Action::SpawnProcess {
             // Announce manager start
             self.bus_tx.send(Evt::State {
                 service: "manager".to_string(),
-                kind: "starting",
+                kind: self.lifecycle.state().as_str(),
                 ts: chrono::Utc::now(),
                 pid: Some(std::process::id()),
             })?;
 
-            // Initial start‑up pass.
-            for (name, tx) in &self.workers {
-                tx.send(Cmd::Start)?;
-                info!("Started service: {name}");
-            }
+            // Initial start‑up pass: dependencies first, dependents as soon
+            // as everything they need reports ready.
+            self.awaiting = self
+                .graph
+                .start_order()
+                .iter()
+                .filter(|name| self.workers.contains_key(*name))
+                .cloned()
+                .collect();
+            self.start_ready();
 
             // Manager is now running
+            self.lifecycle.step(Event::StartedOk);
             self.bus_tx.send(Evt::State {
                 service: "manager".to_string(),
-                kind: "running",
+                kind: self.lifecycle.state().as_str(),
                 ts: chrono::Utc::now(),
                 pid: Some(std::process::id()),
             })?;
         }
 
-        let sig_tick = tick(Duration::from_millis(200));
-        let health_tick = tick(Duration::from_secs(30));
         let log_rotate_tick = tick(Duration::from_secs(3600));
         let restart_tick = tick(Duration::from_millis(100));
 
         loop {
             select! {
                 recv(self.bus_rx) -> evt => self.handle_event(evt?)?,
-                recv(sig_tick)    -> _   => {
-                    if let Some(sig) = check_signals() { // coarse polling ≈200 ms
+                recv(signals) -> sig => match sig {
+                    Ok(Signal::SIGHUP) => {
+                        info!("signal SIGHUP – reloading configuration");
+                        if let Err(e) = self.reload(runtime) {
+                            error!("Configuration reload rejected, keeping the running one: {e:#}");
+                        }
+                    }
+                    Ok(sig) => {
                         info!("signal {sig:?} – orderly shutdown");
+                        self.lifecycle.step(Event::CmdStop);
                         self.bus_tx.send(Evt::State {
                             service: "manager".to_string(),
-                            kind: "stopping",
+                            kind: self.lifecycle.state().as_str(),
                             ts: chrono::Utc::now(),
                             pid: Some(std::process::id()),
                         }).ok();
 
                         // Shutdown embedded HTTP servers if running
                         if let Some(servers) = self.embedded_servers.take()
-                            && let Err(e) = shutdown_all_servers(servers).await
+                            && let Err(e) = runtime.block_on(shutdown_all_servers(servers))
                         {
                             log::error!("Error shutting down embedded servers: {}", e);
                         }
 
-                        for tx in self.workers.values() { tx.send(Cmd::Shutdown).ok(); }
+                        self.shutdown_workers();
                         break;
                     }
-                }
-                recv(health_tick) -> _ => {
-                    // Only trigger health checks if lifecycle is running
-                    if self.lifecycle.is_running() {
-                        // Trigger health checks on all services
-                        for tx in self.workers.values() {
-                            tx.send(Cmd::TickHealth).ok();
-                        }
+                    Err(_) => {
+                        error!("Signal delivery stopped – SIGINT, SIGTERM and SIGHUP are no longer handled");
+                        signals = never();
                     }
-                }
+                },
                 recv(log_rotate_tick) -> _ => {
                     // Trigger log rotation on all services
                     for tx in self.workers.values() {
@@ -205,15 +376,31 @@ impl ServiceManager {
                 recv(restart_tick) -> _ => {
                     // Process pending restarts
                     self.process_pending_restarts();
+                    self.resume_overdue_replacements();
                 }
+                recv(requests) -> call => match call {
+                    Ok(api::Call::Request(api::Op::Reload, reply)) => {
+                        let body = match self.reload(runtime) {
+                            Ok(summary) => api::Body::done(summary),
+                            Err(e) => api::Body::error(format!("reload rejected: {e:#}")),
+                        };
+                        reply.send(body).ok();
+                    }
+                    Ok(api::Call::Request(op, reply)) => self.handle_request(op, reply),
+                    Ok(api::Call::Subscribe { filter, history, reply }) => {
+                        reply.send(self.events.subscribe(filter, history)).ok();
+                    }
+                    Err(_) => {}
+                },
             }
         }
 
         // Announce manager stopped
+        self.lifecycle.step(Event::StopDone);
         self.bus_tx
             .send(Evt::State {
                 service: "manager".to_string(),
-                kind: "stopped",
+                kind: self.lifecycle.state().as_str(),
                 ts: chrono::Utc::now(),
                 pid: Some(std::process::id()),
             })
@@ -223,6 +410,7 @@ impl ServiceManager {
     }
 
     fn handle_event(&mut self, evt: Evt) -> Result<()> {
+        self.events.publish(&evt);
         match &evt {
             Evt::State {
                 service,
@@ -231,68 +419,427 @@ impl ServiceManager {
                 pid,
             } => {
                 info!("{service} → {kind} (pid: {pid:?}, ts: {ts})");
-                // Check if any service has died unexpectedly
-                if *kind == "stopped" && service != "manager" {
-                    // Schedule restart
-                    self.schedule_restart(service, 0);
+                // The last words of a removed service's worker change nothing.
+                if service == "manager" || !self.workers.contains_key(service) {
+                    return Ok(());
+                }
+                if let Some(state) = State::from_kind(kind) {
+                    self.states.insert(service.clone(), state);
+                }
+                match *kind {
+                    "running" => {
+                        self.running.insert(service.clone());
+                        if let Some(pid) = pid {
+                            self.processes
+                                .insert(service.clone(), (*pid, Instant::now()));
+                        }
+                        if let Some(tracker) = self.restarts.get_mut(service) {
+                            tracker.on_started(Instant::now());
+                        }
+                        // Run-to-completion services are ready once a run
+                        // succeeded, not while it is still going.
+                        if !self.has_health_check(service) && !self.runs_to_completion(service) {
+                            self.mark_ready(service);
+                        }
+                    }
+                    "stopped" => {
+                        self.running.remove(service);
+                        self.processes.remove(service);
+                        self.health.remove(service);
+                        // A socket-activated service stopped between
+                        // connections still accepts them.
+                        if !self.runs_to_completion(service) && !self.socket_activated(service) {
+                            self.ready.remove(service);
+                        }
+                        self.resume_replaced(service);
+                    }
+                    "failed" => {
+                        self.ready.remove(service);
+                        self.processes.remove(service);
+                        self.health.remove(service);
+                    }
+                    _ => {}
                 }
             }
             Evt::Health {
                 service,
                 healthy,
+                failures,
+                reason,
                 ts,
             } => {
+                self.health.insert(service.clone(), *healthy);
                 if *healthy {
                     info!("{service} health check OK at {ts}");
+                    self.mark_ready(service);
+                } else {
+                    self.ready.remove(service);
+                    error!(
+                        "{service} health check FAILED at {ts} after {failures} attempt(s): {}",
+                        reason.as_deref().unwrap_or("unknown reason")
+                    );
+                    self.on_unhealthy(service, *failures, reason.clone());
+                }
+            }
+            Evt::FailureAction {
+                service,
+                action,
+                ok,
+                detail,
+                ts,
+            } => {
+                let detail = detail.as_deref().unwrap_or("");
+                if *ok {
+                    info!("{service} on_failure '{action}' succeeded at {ts} {detail}");
                 } else {
-                    error!("{service} health check FAILED at {ts}");
-                    // Schedule restart with delay
-                    self.schedule_restart(service, 100);
+                    warn!("{service} on_failure '{action}' failed at {ts} {detail}");
                 }
             }
+            Evt::Resources {
+                service,
+                memory_bytes,
+                cpu_usec,
+                ts,
+            } => {
+                debug!(
+                    "{service} uses {} MiB, {:.1}s CPU at {ts}",
+                    memory_bytes / (1024 * 1024),
+                    *cpu_usec as f64 / 1e6
+                );
+            }
+            Evt::OomKill { service, kills, ts } => {
+                error!("{service} hit its memory limit: OOM kill #{kills} at {ts}");
+            }
             Evt::LogRotate { service, ts } => {
                 info!("{service} rotated logs at {ts}");
             }
+            Evt::Exit {
+                service,
+                pid,
+                exit,
+                runtime,
+                expected,
+                ts,
+            } => {
+                match exit {
+                    Some(exit) => {
+                        info!("{service} (pid {pid}) exited with {exit} after {runtime:?} at {ts}");
+                    }
+                    None => warn!("{service} (pid {pid}) exited with unknown status at {ts}"),
+                }
+                if self.runs_to_completion(service) {
+                    let run = LastRun::new(*exit, *runtime, *ts);
+                    let succeeded = run.succeeded();
+                    self.jobs.entry(service.clone()).or_default().last_run = Some(run);
+                    if succeeded {
+                        info!("{service} completed successfully in {runtime:?}");
+                        self.mark_ready(service);
+                        return Ok(());
+                    }
+                }
+                if self.socket_activated(service) && !expected && exit.is_some_and(|e| e.success())
+                {
+                    // Back to waiting for connections, not a crash.
+                    return Ok(());
+                }
+                // The worker follows up with an unhealthy report; remember
+                // whether this was a clean exit for the restart policy.
+                if !expected && exit.is_some_and(|e| e.success()) {
+                    self.clean_exits.insert(service.clone());
+                } else {
+                    self.clean_exits.remove(service);
+                }
+            }
+            Evt::Scheduled {
+                service,
+                next_run,
+                ts,
+            } => {
+                info!("{service} next runs at {next_run} (scheduled at {ts})");
+                self.jobs.entry(service.clone()).or_default().next_run = Some(*next_run);
+                // Dependents of a scheduled job need not wait for its first run.
+                self.mark_ready(service);
+            }
+            Evt::Listening {
+                service,
+                listen,
+                ts,
+            } => {
+                info!("{service} listening on {} at {ts}", listen.join(", "));
+                // Connections queue up until the service has started.
+                self.mark_ready(service);
+            }
             Evt::Fatal { service, msg, ts } => {
                 error!("{service} FATAL at {ts}: {msg}");
+                if service == "manager" {
+                    return Ok(());
+                }
                 // Notify about fatal error
                 let error_msg = format!("Service {service} encountered fatal error: {msg}");
                 self.bus_tx
-                    .send(Evt::Fatal {
+                    .try_send(Evt::Fatal {
                         service: "manager".to_string(),
                         msg: Box::leak(error_msg.into_boxed_str()) as &'static str,
                         ts: chrono::Utc::now(),
                     })
                     .ok();
-                // Schedule restart with longer delay
-                self.schedule_restart(service, 1000);
+                self.schedule_restart(service, true).ok();
             }
         }
         Ok(())
     }
 
-    /// Schedule a service for restart after a delay
-    fn schedule_restart(&mut self, service: &str, delay_ms: u64) {
-        if let Some(tx) = self.workers.get(service) {
-            // Send stop command immediately
-            tx.send(Cmd::Stop).ok();
-
-            // Schedule the restart
-            let restart_time = Instant::now() + Duration::from_millis(delay_ms);
-            let attempts = self
-                .pending_restarts
-                .get(service)
-                .map_or(1, |s| s.attempts + 1);
-
-            self.pending_restarts.insert(
-                service.to_string(),
-                RestartState {
-                    stop_time: restart_time,
-                    attempts,
-                },
-            );
+    /// React to a service turning unhealthy: run its `on_failure` actions,
+    /// or fall back to a plain restart when none are configured.
+    fn on_unhealthy(&mut self, service: &str, attempts: u32, reason: Option<String>) {
+        let failure = !self.clean_exits.remove(service);
+        let actions = self
+            .failure_actions
+            .get(service)
+            .cloned()
+            .unwrap_or_default();
+        if actions.is_empty() {
+            self.schedule_restart(service, failure).ok();
+            return;
+        }
+
+        let ctx = FailureContext {
+            service: service.to_string(),
+            reason: reason.unwrap_or_else(|| "unknown reason".to_string()),
+            attempts,
+            ts: chrono::Utc::now(),
+        };
+        for action in actions {
+            match action {
+                FailureAction::Restart => {
+                    let outcome = self.schedule_restart(service, failure);
+                    self.report_action(service, &action, outcome);
+                }
+                FailureAction::StopDependents => {
+                    let stopped = self.stop_dependents(service);
+                    let outcome = if stopped.is_empty() {
+                        Err("no running dependents to stop".to_string())
+                    } else {
+                        Ok(format!("stopped [{}]", stopped.join(", ")))
+                    };
+                    self.report_action(service, &action, outcome);
+                }
+                FailureAction::Exec(_) | FailureAction::Webhook(_) => {
+                    on_failure::spawn_external(action, ctx.clone(), self.bus_tx.clone());
+                }
+            }
+        }
+    }
+
+    /// Announce the outcome of an in‑process `on_failure` action: what it
+    /// did, or why it did nothing.
+    fn report_action(
+        &self,
+        service: &str,
+        action: &FailureAction,
+        outcome: Result<String, String>,
+    ) {
+        let (ok, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        // `try_send`: the manager is the bus consumer and must never block on it.
+        self.bus_tx
+            .try_send(Evt::FailureAction {
+                service: service.to_string(),
+                action: action.to_string(),
+                ok,
+                detail: Some(detail),
+                ts: chrono::Utc::now(),
+            })
+            .ok();
+    }
+
+    /// Stop every dependent of `service` until it is healthy again.
+    /// Returns the dependents stopped now, not those already held.
+    fn stop_dependents(&mut self, service: &str) -> Vec<String> {
+        let mut stopped = Vec::new();
+        for dep in self.graph.dependents(service) {
+            if self.held.contains_key(&dep) {
+                continue;
+            }
+            self.held.insert(dep.clone(), service.to_string());
+            if let Some(tracker) = self.restarts.get_mut(&dep) {
+                tracker.next_at = None;
+            }
+            self.awaiting.retain(|name| *name != dep);
+            self.ready.remove(&dep);
+            if let Some(tx) = self.workers.get(&dep) {
+                tx.send(Cmd::Stop).ok();
+                info!("Stopped {dep} (depends on failed {service})");
+                stopped.push(dep);
+            }
+        }
+        stopped
+    }
+
+    fn runs_to_completion(&self, service: &str) -> bool {
+        self.definitions
+            .get(service)
+            .is_some_and(ServiceDefinition::runs_to_completion)
+    }
+
+    fn socket_activated(&self, service: &str) -> bool {
+        self.definitions
+            .get(service)
+            .is_some_and(ServiceDefinition::socket_activated)
+    }
+
+    fn has_health_check(&self, service: &str) -> bool {
+        self.definitions
+            .get(service)
+            .is_some_and(|def| def.health_check.is_some())
+    }
+
+    /// Record that `service` can be relied on, release the dependents held
+    /// back while it was down, and start whatever is now unblocked.
+    fn mark_ready(&mut self, service: &str) {
+        if !self.ready.insert(service.to_string()) {
+            return;
+        }
+        let released: Vec<String> = self
+            .held
+            .iter()
+            .filter(|(_, by)| by.as_str() == service)
+            .map(|(dep, _)| dep.clone())
+            .collect();
+        for dep in released {
+            self.held.remove(&dep);
+            info!("Releasing {dep} ({service} recovered)");
+            if !self.awaiting.contains(&dep) {
+                self.awaiting.push(dep);
+            }
+        }
+        let graph = &self.graph;
+        self.awaiting.sort_by_key(|name| graph.rank(name));
+        self.start_ready();
+    }
+
+    /// Start every awaiting service whose dependencies are all ready.
+    fn start_ready(&mut self) {
+        let (startable, waiting): (Vec<String>, Vec<String>) = std::mem::take(&mut self.awaiting)
+            .into_iter()
+            .partition(|name| {
+                self.graph
+                    .dependencies(name)
+                    .iter()
+                    .all(|dep| self.ready.contains(dep))
+            });
+        self.awaiting = waiting;
+        for name in startable {
+            if let Some(tx) = self.workers.get(&name) {
+                tx.send(Cmd::Start).ok();
+                info!("Started service: {name}");
+            }
+        }
+    }
 
-            info!("Scheduled restart for {service} in {delay_ms}ms (attempt #{attempts})");
+    /// Shut workers down in reverse dependency order, waiting for each
+    /// service that is not stopped – starting, running, restarting or
+    /// stopping alike – to report stopped before moving on to the services
+    /// it depends on.
+    fn shutdown_workers(&mut self) {
+        // States reported before the shutdown began count too.
+        while let Ok(evt) = self.bus_rx.try_recv() {
+            self.track_shutdown(&evt);
+        }
+        let order: Vec<String> = self.graph.start_order().iter().rev().cloned().collect();
+        for name in order {
+            let Some(tx) = self.workers.get(&name) else {
+                continue;
+            };
+            tx.send(Cmd::Shutdown).ok();
+
+            let grace = self
+                .definitions
+                .get(&name)
+                .and_then(|def| def.stop_timeout_s)
+                .map_or(DEFAULT_STOP_TIMEOUT, Duration::from_secs)
+                + SHUTDOWN_MARGIN;
+            let deadline = Instant::now() + grace;
+            while self
+                .states
+                .get(&name)
+                .is_some_and(|state| *state != State::Stopped)
+            {
+                match self.bus_rx.recv_deadline(deadline) {
+                    Ok(evt) => self.track_shutdown(&evt),
+                    Err(_) => {
+                        warn!("{name} did not stop within {grace:?}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record the state changes of `evt` during shutdown.
+    fn track_shutdown(&mut self, evt: &Evt) {
+        self.events.publish(evt);
+        if let Evt::State { service, kind, .. } = evt
+            && self.workers.contains_key(service)
+            && let Some(state) = State::from_kind(kind)
+        {
+            if state == State::Stopped {
+                info!("{service} stopped");
+                self.running.remove(service);
+                self.processes.remove(service);
+            }
+            self.states.insert(service.clone(), state);
+        }
+    }
+
+    /// A service went down (`failure` unless it exited cleanly): ask its
+    /// restart policy whether, and after which backoff, it comes back.
+    /// Dependents are held until it is ready again.  Returns what was
+    /// scheduled, or why nothing was.
+    fn schedule_restart(&mut self, service: &str, failure: bool) -> Result<String, String> {
+        let Some(tracker) = self.restarts.get_mut(service) else {
+            return Err("service is gone".to_string());
+        };
+        if tracker.next_at.is_some() {
+            return Err("a restart is already pending".to_string());
+        }
+        if tracker.is_failed() {
+            return Err("restarts were given up".to_string());
+        }
+        match tracker.on_down(failure, Instant::now()) {
+            Decision::Skip => {
+                info!("{service} is down – restart policy leaves it stopped");
+                Err("restart policy leaves it stopped".to_string())
+            }
+            Decision::Restart { delay, attempt } => {
+                self.stop_dependents(service);
+                let Some(tx) = self.workers.get(service) else {
+                    return Err("service has no worker".to_string());
+                };
+                tx.send(Cmd::Stop).ok();
+                info!("Scheduled restart for {service} in {delay:?} (attempt #{attempt})");
+                Ok(format!("restart in {delay:?} (attempt #{attempt})"))
+            }
+            Decision::GiveUp { restarts, window } => {
+                self.stop_dependents(service);
+                if let Some(tx) = self.workers.get(service) {
+                    tx.send(Cmd::Stop).ok();
+                }
+                error!("{service} restarted {restarts} times within {window:?} – giving up");
+                self.bus_tx
+                    .try_send(Evt::State {
+                        service: service.to_string(),
+                        kind: "failed",
+                        ts: chrono::Utc::now(),
+                        pid: None,
+                    })
+                    .ok();
+                Err(format!(
+                    "gave up after {restarts} restarts within {window:?}"
+                ))
+            }
         }
     }
 
@@ -302,21 +849,20 @@ impl ServiceManager {
         let mut to_restart = Vec::new();
 
         // Find services ready to restart
-        for (service, state) in &self.pending_restarts {
-            if now >= state.stop_time {
-                to_restart.push(service.clone());
+        for (service, tracker) in &mut self.restarts {
+            if tracker.next_at.is_some_and(|at| now >= at) {
+                tracker.next_at = None;
+                to_restart.push((service.clone(), tracker.attempts()));
             }
         }
 
         // Restart ready services
-        for service in to_restart {
-            if let Some(state) = self.pending_restarts.remove(&service)
-                && let Some(tx) = self.workers.get(&service)
-            {
-                info!("Restarting {} (attempt #{})", service, state.attempts);
+        for (service, attempts) in to_restart {
+            if let Some(tx) = self.workers.get(&service) {
+                info!("Restarting {service} (attempt #{attempts})");
                 tx.send(Cmd::Start).ok();
                 self.bus_tx
-                    .send(Evt::State {
+                    .try_send(Evt::State {
                         service: "manager".to_string(),
                         kind: "restarted-service",
                         ts: chrono::Utc::now(),
@@ -328,59 +874,99 @@ impl ServiceManager {
     }
 }
 
-// Cheap, polling‑based Unix signal handling (lock‑free).
-static RECEIVED_SIGNAL: std::sync::LazyLock<std::sync::atomic::AtomicUsize> =
-    std::sync::LazyLock::new(|| std::sync::atomic::AtomicUsize::new(0));
+/// Every service definition of a configuration, and what could not be
+/// loaded.
+struct Loaded {
+    definitions: HashMap<String, ServiceDefinition>,
+    templates: Templates,
+    problems: Vec<String>,
+}
 
-pub fn install_signal_handlers() -> anyhow::Result<()> {
-    use nix::sys::signal::{self, Signal};
-    extern "C" fn handler(sig: i32) {
-        RECEIVED_SIGNAL.store(sig as usize, std::sync::atomic::Ordering::SeqCst);
+/// Gather the inline services, the `services_dir` files and the template
+/// instances of `cfg`, keyed by name.
+fn load_definitions(cfg: &ServiceConfig) -> Loaded {
+    let (loaded, templates, mut problems) = load_services_dir(cfg);
+    let instances = cfg
+        .instances
+        .iter()
+        .filter_map(|name| match templates.instantiate(name) {
+            Ok(def) => Some(def),
+            Err(e) => {
+                problems.push(format!("instance '{name}': {e}"));
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut definitions: HashMap<String, ServiceDefinition> = HashMap::new();
+    for def in cfg.services.iter().cloned().chain(loaded).chain(instances) {
+        if let Some(prev) = definitions.insert(def.name.clone(), def) {
+            warn!(
+                "Service '{}' defined more than once – last definition wins",
+                prev.name
+            );
+        }
     }
-    unsafe {
-        signal::sigaction(
-            Signal::SIGINT,
-            &signal::SigAction::new(
-                signal::SigHandler::Handler(handler),
-                signal::SaFlags::empty(),
-                signal::SigSet::empty(),
-            ),
-        )
-        .map_err(|e| anyhow::anyhow!(
-            "Failed to register SIGINT handler: {e}. Signal handling is required for daemon operation."
-        ))?;
-        signal::sigaction(
-            Signal::SIGTERM,
-            &signal::SigAction::new(
-                signal::SigHandler::Handler(handler),
-                signal::SaFlags::empty(),
-                signal::SigSet::empty(),
-            ),
-        )
-        .map_err(|e| anyhow::anyhow!(
-            "Failed to register SIGTERM handler: {e}. Signal handling is required for daemon operation."
-        ))?;
+    definitions.retain(|name, def| {
+        let Some(Err(e)) = def
+            .socket_activation
+            .as_ref()
+            .map(activation::check_supported)
+        else {
+            return true;
+        };
+        problems.push(format!("service '{name}': {e}"));
+        false
+    });
+    Loaded {
+        definitions,
+        templates,
+        problems,
     }
-    Ok(())
 }
 
-/// Non‑blocking check – returns Some(signal) once.
-fn check_signals() -> Option<nix::sys::signal::Signal> {
-    use std::sync::atomic::Ordering::AcqRel;
-
-    use nix::sys::signal::Signal;
-    let val = RECEIVED_SIGNAL.swap(0, AcqRel);
-    if val == 0 {
-        None
-    } else {
-        // Try to convert signal number - if it fails (which should never happen since we stored it),
-        // log the error and return None to avoid crashing the daemon
-        match Signal::try_from(val as i32) {
-            Ok(sig) => Some(sig),
+/// Parse every `*.toml` in `services_dir` into a service definition, or a
+/// template if it is named `<prefix>@.toml`.  Files that cannot be read or
+/// parsed are returned as problems.
+fn load_services_dir(cfg: &ServiceConfig) -> (Vec<ServiceDefinition>, Templates, Vec<String>) {
+    let mut defs = Vec::new();
+    let mut templates = Templates::default();
+    let mut problems = Vec::new();
+    let Some(services_dir) = &cfg.services_dir else {
+        return (defs, templates, problems);
+    };
+    let entries = match std::fs::read_dir(services_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            problems.push(format!("services_dir {services_dir}: {e}"));
+            return (defs, templates, problems);
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("toml") {
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match toml::from_str::<ServiceDefinition>(&content) {
+                Ok(def) => match templates::template_prefix(&path) {
+                    Some(prefix) => {
+                        info!("Loaded template '{prefix}@' from {}", path.display());
+                        templates.insert(prefix, def);
+                    }
+                    None => {
+                        info!("Loaded service '{}' from {}", def.name, path.display());
+                        defs.push(def);
+                    }
+                },
+                Err(e) => {
+                    problems.push(format!("service file {}: {e}", path.display()));
+                }
+            },
             Err(e) => {
-                log::error!("BUG: Invalid signal number {val} from signal handler: {e}. This indicates a programming error.");
-                None
+                problems.push(format!("service file {}: {e}", path.display()));
             }
         }
     }
+    (defs, templates, problems)
 }
@@ -0,0 +1,197 @@
+//! Service dependency graph built from `ServiceDefinition::depends_on`.
+//!
+//! Built once at load time: unknown names and cycles are rejected before any
+//! worker is spawned.  The resulting topological order drives start‑up
+//! (dependencies first) and shutdown (reverse).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use thiserror::Error;
+
+use crate::config::ServiceDefinition;
+
+/// Invalid `depends_on` configuration.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DependencyError {
+    #[error("service '{service}' depends on unknown service '{dependency}'")]
+    Unknown { service: String, dependency: String },
+
+    #[error("dependency cycle: {}", .0.join(" → "))]
+    Cycle(Vec<String>),
+}
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    /// Topological order – every service comes after its dependencies.
+    order: Vec<String>,
+    deps: HashMap<String, Vec<String>>,
+    dependents: HashMap<String, Vec<String>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+impl DependencyGraph {
+    pub fn build<'a>(
+        defs: impl IntoIterator<Item = &'a ServiceDefinition>,
+    ) -> Result<Self, DependencyError> {
+        let mut deps: HashMap<String, Vec<String>> = HashMap::new();
+        for def in defs {
+            let mut list = def.depends_on.clone();
+            list.sort();
+            list.dedup();
+            deps.insert(def.name.clone(), list);
+        }
+
+        let mut names: Vec<&String> = deps.keys().collect();
+        names.sort();
+        for name in &names {
+            if let Some(missing) = deps[*name].iter().find(|d| !deps.contains_key(*d)) {
+                return Err(DependencyError::Unknown {
+                    service: (*name).clone(),
+                    dependency: missing.clone(),
+                });
+            }
+        }
+
+        let mut order = Vec::with_capacity(deps.len());
+        let mut marks: HashMap<&str, Mark> = HashMap::new();
+        let mut path: Vec<&str> = Vec::new();
+        for name in names {
+            visit(name, &deps, &mut marks, &mut path, &mut order)?;
+        }
+
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        for name in &order {
+            for dep in &deps[name] {
+                dependents
+                    .entry(dep.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+        }
+
+        Ok(Self {
+            order,
+            deps,
+            dependents,
+        })
+    }
+
+    /// Every service, dependencies before dependents.
+    #[must_use]
+    pub fn start_order(&self) -> &[String] {
+        &self.order
+    }
+
+    /// Direct dependencies of `service`.
+    #[must_use]
+    pub fn dependencies(&self, service: &str) -> &[String] {
+        self.deps.get(service).map_or(&[], Vec::as_slice)
+    }
+
+    /// Services that depend on `service`, directly or transitively, in
+    /// start order.
+    #[must_use]
+    pub fn dependents(&self, service: &str) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([service]);
+        while let Some(current) = queue.pop_front() {
+            for dependent in self.dependents.get(current).into_iter().flatten() {
+                if seen.insert(dependent.as_str()) {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+        self.order
+            .iter()
+            .filter(|name| seen.contains(name.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Position of `service` in the start order (unknown names sort last).
+    #[must_use]
+    pub fn rank(&self, service: &str) -> usize {
+        self.order
+            .iter()
+            .position(|name| name == service)
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// Depth‑first post‑order walk; `path` holds the current chain so a back
+/// edge can be reported as the exact cycle.
+fn visit<'a>(
+    name: &'a str,
+    deps: &'a HashMap<String, Vec<String>>,
+    marks: &mut HashMap<&'a str, Mark>,
+    path: &mut Vec<&'a str>,
+    order: &mut Vec<String>,
+) -> Result<(), DependencyError> {
+    match marks.get(name) {
+        Some(Mark::Done) => return Ok(()),
+        Some(Mark::Visiting) => {
+            let start = path.iter().position(|n| *n == name).unwrap_or(0);
+            let mut cycle: Vec<String> = path[start..].iter().map(|n| (*n).to_string()).collect();
+            cycle.push(name.to_string());
+            return Err(DependencyError::Cycle(cycle));
+        }
+        None => {}
+    }
+
+    marks.insert(name, Mark::Visiting);
+    path.push(name);
+    for dep in &deps[name] {
+        visit(dep, deps, marks, path, order)?;
+    }
+    path.pop();
+    marks.insert(name, Mark::Done);
+    order.push(name.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(name: &str, depends_on: &[&str]) -> ServiceDefinition {
+        toml::from_str(&format!(
+            "name = {name:?}\ncommand = \"true\"\ndepends_on = {depends_on:?}"
+        ))
+        .expect("valid definition")
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        let defs = [
+            def("web", &["db", "cache"]),
+            def("cache", &[]),
+            def("db", &[]),
+        ];
+        let graph = DependencyGraph::build(&defs).expect("acyclic");
+        assert_eq!(graph.start_order(), ["cache", "db", "web"]);
+        assert_eq!(graph.dependents("db"), ["web"]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_cycles() {
+        let unknown = [def("web", &["db"])];
+        assert_eq!(
+            DependencyGraph::build(&unknown).unwrap_err(),
+            DependencyError::Unknown {
+                service: "web".into(),
+                dependency: "db".into(),
+            }
+        );
+
+        let cyclic = [def("a", &["b"]), def("b", &["c"]), def("c", &["a"])];
+        assert_eq!(
+            DependencyGraph::build(&cyclic).unwrap_err(),
+            DependencyError::Cycle(vec!["a".into(), "b".into(), "c".into(), "a".into()])
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Fan-out of bus events to subscribers.
+//!
+//! Every subscriber has a bounded queue of its own, filled with `try_send`,
+//! so a slow one can never block the manager.  Events that do not fit are
+//! dropped and counted; the subscriber learns how many it missed
+//! ([`StreamEvent::Lagged`]) as soon as its queue has room again.
+//! Subscribers that went away are forgotten on the next event.
+
+use std::collections::VecDeque;
+
+use crossbeam_channel::{Receiver, Sender, TrySendError, bounded};
+
+use crate::api::{EventFilter, StreamEvent};
+use crate::ipc::Evt;
+
+/// Events queued per subscriber before it counts as lagging.
+const SUBSCRIBER_BOUND: usize = 256;
+
+/// Recent events kept for `events` requests and new subscribers.
+const HISTORY: usize = 256;
+
+#[derive(Debug)]
+struct Subscriber {
+    filter: EventFilter,
+    tx: Sender<StreamEvent>,
+    /// Events dropped since the last one delivered.
+    missed: u64,
+}
+
+impl Subscriber {
+    /// Queue `event`; false once the subscriber is gone.
+    fn offer(&mut self, event: &StreamEvent) -> bool {
+        if self.missed > 0 {
+            match self.tx.try_send(StreamEvent::Lagged {
+                missed: self.missed,
+            }) {
+                Ok(()) => self.missed = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.missed += 1;
+                    return true;
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        match self.tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.missed += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventHub {
+    subscribers: Vec<Subscriber>,
+    recent: VecDeque<StreamEvent>,
+}
+
+impl EventHub {
+    /// A queue of the events matching `filter`, starting with up to
+    /// `history` recent ones.
+    pub fn subscribe(&mut self, filter: EventFilter, history: usize) -> Receiver<StreamEvent> {
+        let (tx, rx) = bounded(SUBSCRIBER_BOUND);
+        for event in self.recent(&filter, history.min(SUBSCRIBER_BOUND)) {
+            tx.try_send(event).ok();
+        }
+        self.subscribers.push(Subscriber {
+            filter,
+            tx,
+            missed: 0,
+        });
+        rx
+    }
+
+    /// The newest `limit` events matching `filter`, oldest first.
+    #[must_use]
+    pub fn recent(&self, filter: &EventFilter, limit: usize) -> Vec<StreamEvent> {
+        let mut events: Vec<StreamEvent> = self
+            .recent
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(limit)
+            .cloned()
+            .collect();
+        events.reverse();
+        events
+    }
+
+    pub fn publish(&mut self, evt: &Evt) {
+        let Some(event) = StreamEvent::from_evt(evt) else {
+            return;
+        };
+        self.subscribers
+            .retain_mut(|sub| !sub.filter.matches(&event) || sub.offer(&event));
+        if self.recent.len() == HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotated(service: &str) -> Evt {
+        Evt::LogRotate {
+            service: service.to_string(),
+            ts: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn slow_subscribers_lag_instead_of_blocking() {
+        let mut hub = EventHub::default();
+        let filter = EventFilter {
+            services: vec!["web".into()],
+            ..EventFilter::default()
+        };
+        let events = hub.subscribe(filter, 0);
+        for _ in 0..SUBSCRIBER_BOUND + 10 {
+            hub.publish(&rotated("web"));
+            hub.publish(&rotated("db"));
+        }
+        assert_eq!(events.len(), SUBSCRIBER_BOUND);
+        for _ in 0..SUBSCRIBER_BOUND {
+            events.recv().expect("queued");
+        }
+
+        hub.publish(&rotated("web"));
+        assert_eq!(events.recv().ok(), Some(StreamEvent::Lagged { missed: 10 }));
+        assert!(matches!(
+            events.recv(),
+            Ok(StreamEvent::LogRotate { service, .. }) if service == "web"
+        ));
+
+        drop(events);
+        hub.publish(&rotated("web"));
+        assert!(hub.subscribers.is_empty());
+        assert_eq!(hub.recent(&EventFilter::default(), 3).len(), 3);
+    }
+}
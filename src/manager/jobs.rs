@@ -0,0 +1,44 @@
+//! Run history of services that run to completion (`oneshot` and
+//! `scheduled`).
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::ipc::ExitInfo;
+
+/// Last and next run of a `oneshot` or `scheduled` service.
+#[derive(Debug, Clone, Default)]
+pub struct JobStatus {
+    pub last_run: Option<LastRun>,
+    /// When a `scheduled` service runs next.
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // Public API - exported in lib.rs, read by external consumers
+pub struct LastRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration: Duration,
+    /// `None` if the process could not be reaped.
+    pub exit: Option<ExitInfo>,
+}
+
+impl LastRun {
+    #[must_use]
+    pub fn new(exit: Option<ExitInfo>, duration: Duration, finished_at: DateTime<Utc>) -> Self {
+        Self {
+            started_at: chrono::Duration::from_std(duration)
+                .map_or(finished_at, |d| finished_at - d),
+            finished_at,
+            duration,
+            exit,
+        }
+    }
+
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.exit.is_some_and(|e| e.success())
+    }
+}
@@ -0,0 +1,178 @@
+//! `HealthCheckConfig::on_failure` actions.
+//!
+//! Each entry is one of:
+//!
+//! * `restart`          – restart the unhealthy service
+//! * `stop-dependents`  – stop every service that (transitively) depends on it
+//! * `exec:<command>`   – run `<command>` through `sh -c`
+//! * `webhook:<url>`    – POST a JSON payload to `<url>`
+//!
+//! External actions receive the service name, failure reason and attempt
+//! count as `KODEGEN_SERVICE`, `KODEGEN_FAILURE_REASON` and
+//! `KODEGEN_FAILURE_ATTEMPTS` plus the same data as JSON (on stdin for
+//! `exec`, as the request body for `webhook`).  They run on their own thread
+//! and report their outcome on the bus as `Evt::FailureAction`.
+
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use log::error;
+use serde::Serialize;
+
+use crate::ipc::Evt;
+
+/// Upper bound for a single `exec:` or `webhook:` action.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One parsed `on_failure` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureAction {
+    Restart,
+    StopDependents,
+    Exec(String),
+    Webhook(String),
+}
+
+impl FromStr for FailureAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "restart" => return Ok(Self::Restart),
+            "stop-dependents" => return Ok(Self::StopDependents),
+            _ => {}
+        }
+        match s.split_once(':') {
+            Some(("exec", cmd)) if !cmd.trim().is_empty() => Ok(Self::Exec(cmd.trim().to_string())),
+            Some(("webhook", url)) if !url.trim().is_empty() => {
+                Ok(Self::Webhook(url.trim().to_string()))
+            }
+            _ => Err(format!(
+                "unknown on_failure action '{s}' (expected restart, stop-dependents, exec:<cmd> or webhook:<url>)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FailureAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Restart => f.write_str("restart"),
+            Self::StopDependents => f.write_str("stop-dependents"),
+            Self::Exec(cmd) => write!(f, "exec:{cmd}"),
+            Self::Webhook(url) => write!(f, "webhook:{url}"),
+        }
+    }
+}
+
+/// Parse a service's `on_failure` list, logging and skipping invalid entries.
+pub fn parse_actions(service: &str, raw: &[String]) -> Vec<FailureAction> {
+    raw.iter()
+        .filter_map(|entry| match entry.parse() {
+            Ok(action) => Some(action),
+            Err(e) => {
+                error!("{service}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// What an action is told about the failure.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureContext {
+    pub service: String,
+    pub reason: String,
+    pub attempts: u32,
+    pub ts: DateTime<Utc>,
+}
+
+/// Run an `exec:` or `webhook:` action in the background and report the
+/// outcome on `bus`.  In‑process actions are handled by the manager itself.
+pub fn spawn_external(action: FailureAction, ctx: FailureContext, bus: Sender<Evt>) {
+    let service = ctx.service.clone();
+    let spawned = thread::Builder::new()
+        .name(format!("on-failure-{service}"))
+        .spawn(move || {
+            let outcome = match &action {
+                FailureAction::Exec(cmd) => run_exec(cmd, &ctx),
+                FailureAction::Webhook(url) => run_webhook(url, &ctx),
+                FailureAction::Restart | FailureAction::StopDependents => return,
+            };
+            let (ok, detail) = match outcome {
+                Ok(detail) => (true, detail),
+                Err(detail) => (false, detail),
+            };
+            bus.send(Evt::FailureAction {
+                service: ctx.service,
+                action: action.to_string(),
+                ok,
+                detail: Some(detail),
+                ts: Utc::now(),
+            })
+            .ok();
+        });
+    if let Err(e) = spawned {
+        error!("{service}: cannot spawn on_failure thread: {e}");
+    }
+}
+
+fn run_exec(cmd: &str, ctx: &FailureContext) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("KODEGEN_SERVICE", &ctx.service)
+        .env("KODEGEN_FAILURE_REASON", &ctx.reason)
+        .env("KODEGEN_FAILURE_ATTEMPTS", ctx.attempts.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("spawn failed: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A script that ignores stdin may exit before reading it – not an error.
+        serde_json::to_writer(&mut stdin, ctx).ok();
+        stdin.write_all(b"\n").ok();
+    }
+
+    let deadline = Instant::now() + ACTION_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(format!("exited with {status}")),
+            Ok(Some(status)) => return Err(format!("exited with {status}")),
+            Ok(None) if Instant::now() >= deadline => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("timed out after {}s", ACTION_TIMEOUT.as_secs()));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(format!("wait failed: {e}")),
+        }
+    }
+}
+
+fn run_webhook(url: &str, ctx: &FailureContext) -> Result<String, String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(ACTION_TIMEOUT)
+        .build()
+        .map_err(|e| format!("HTTP client error: {e}"))?;
+    let resp = client
+        .post(url)
+        .json(ctx)
+        .send()
+        .map_err(|e| format!("POST failed: {e}"))?;
+    let status = resp.status();
+    if status.is_success() {
+        Ok(format!("HTTP {status}"))
+    } else {
+        Err(format!("HTTP {status}"))
+    }
+}
@@ -0,0 +1,408 @@
+//! Configuration reload (SIGHUP or a control socket `reload`): re-read
+//! `kodegend.toml` and `services_dir` and bring the running set in line.
+//!
+//! Instances added at runtime are kept, instantiated anew from their
+//! possibly changed template, unless the template is gone.
+//!
+//! Everything that can fail – parsing, template instances, the dependency
+//! graph, spawning the new workers – happens before anything changes, so an
+//! invalid configuration leaves the running one untouched.  Then added
+//! services start, removed ones stop, changed ones restart with their new
+//! definition and all others keep running.  Category servers are kept if
+//! their port and `enabled` flag are unchanged and restarted otherwise.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::Sender;
+use log::{error, info, warn};
+use tokio::runtime::Handle;
+
+use crate::config::{CategoryServerConfig, ServiceConfig, ServiceDefinition};
+use crate::ipc::Cmd;
+use crate::service::WorkerDefaults;
+use crate::service::embedded_servers::{EmbeddedServer, shutdown_all_servers, start_all_servers};
+use crate::service::process::DEFAULT_STOP_TIMEOUT;
+use crate::state_machine::State;
+
+use super::deps::DependencyGraph;
+use super::on_failure;
+use super::restart::RestartTracker;
+use super::templates::Templates;
+use super::{Loaded, SHUTDOWN_MARGIN, ServiceManager, load_definitions};
+
+/// How a new set of definitions differs from the running one.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Diff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+impl Diff {
+    /// With `defaults_changed` – the daemon‑wide worker settings differ –
+    /// every service present in both counts as changed.
+    fn between(
+        old: &HashMap<String, ServiceDefinition>,
+        new: &HashMap<String, ServiceDefinition>,
+        defaults_changed: bool,
+    ) -> Self {
+        let mut diff = Self::default();
+        for (name, def) in new {
+            match old.get(name) {
+                None => diff.added.push(name.clone()),
+                Some(prev) if defaults_changed || prev != def => diff.changed.push(name.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .keys()
+            .filter(|name| !new.contains_key(*name))
+            .cloned()
+            .collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+}
+
+/// Add the runtime-added `instances` the configuration does not define to
+/// `definitions`, instantiated from the reloaded `templates`.  An instance
+/// whose template is gone is left out, and so removed.
+fn carry_instances(
+    instances: &HashSet<String>,
+    templates: &Templates,
+    definitions: &mut HashMap<String, ServiceDefinition>,
+) {
+    for name in instances {
+        if definitions.contains_key(name) {
+            continue;
+        }
+        match templates.instantiate(name) {
+            Ok(def) => {
+                definitions.insert(name.clone(), def);
+            }
+            Err(e) => warn!("Dropping instance {name}: {e}"),
+        }
+    }
+}
+
+impl ServiceManager {
+    /// Re-read the configuration file and reconcile; returns a summary of
+    /// what changed.
+    pub(super) fn reload(&mut self, runtime: &Handle) -> Result<String> {
+        let path = self
+            .config_path
+            .clone()
+            .context("no configuration file to reload")?;
+        let raw = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let cfg: ServiceConfig =
+            toml::from_str(&raw).with_context(|| format!("Failed to parse {}", path.display()))?;
+        let Loaded {
+            mut definitions,
+            templates,
+            problems,
+        } = load_definitions(&cfg);
+        if !problems.is_empty() {
+            anyhow::bail!("invalid {}", problems.join("; "));
+        }
+        carry_instances(&self.added_instances, &templates, &mut definitions);
+        let graph =
+            DependencyGraph::build(definitions.values()).context("Invalid service dependencies")?;
+        let defaults = WorkerDefaults::from(&cfg);
+        let auto_restart = cfg.auto_restart.unwrap_or(true);
+        let diff = Diff::between(&self.definitions, &definitions, defaults != self.defaults);
+
+        // New workers idle until told to start; if one cannot be spawned,
+        // the others go again and nothing has changed.
+        let mut spawned = HashMap::new();
+        for name in diff.added.iter().chain(&diff.changed) {
+            match crate::service::spawn(definitions[name].clone(), self.bus_tx.clone(), &defaults) {
+                Ok(tx) => {
+                    spawned.insert(name.clone(), tx);
+                }
+                Err(e) => {
+                    for tx in spawned.values() {
+                        tx.send(Cmd::Shutdown).ok();
+                    }
+                    return Err(e).with_context(|| format!("Failed to spawn service '{name}'"));
+                }
+            }
+        }
+
+        // Whether each changed service should come back, decided while its
+        // old state is still known.
+        let resume: Vec<(String, bool)> = diff
+            .changed
+            .iter()
+            .map(|name| (name.clone(), self.is_active(name)))
+            .collect();
+
+        for name in &diff.removed {
+            self.remove_service(name);
+        }
+        // The restart policy of services without one follows `auto_restart`.
+        let policies_changed = auto_restart != self.auto_restart;
+        for (name, def) in &definitions {
+            if policies_changed || spawned.contains_key(name) {
+                self.restarts
+                    .insert(name.clone(), RestartTracker::new(def, auto_restart));
+            }
+            if spawned.contains_key(name) {
+                match &def.health_check {
+                    Some(hc) => {
+                        self.failure_actions.insert(
+                            name.clone(),
+                            on_failure::parse_actions(name, &hc.on_failure),
+                        );
+                    }
+                    None => {
+                        self.failure_actions.remove(name);
+                    }
+                }
+            }
+        }
+        self.definitions = definitions;
+        self.graph = graph;
+        self.templates = templates;
+        self.defaults = defaults;
+        self.auto_restart = auto_restart;
+
+        for (name, active) in resume {
+            if let Some(tx) = spawned.remove(&name) {
+                self.replace_worker(&name, tx, active);
+            }
+        }
+        for (name, tx) in spawned {
+            info!("Added service {name}");
+            self.workers.insert(name.clone(), tx);
+            self.awaiting.push(name);
+        }
+        let graph = &self.graph;
+        self.awaiting.sort_by_key(|name| graph.rank(name));
+        self.start_ready();
+
+        let (started, stopped) =
+            runtime.block_on(self.reconcile_category_servers(&cfg.category_servers));
+        Ok(format!(
+            "services added [{}], removed [{}], restarted [{}]; category servers started [{}], stopped [{}]",
+            diff.added.join(", "),
+            diff.removed.join(", "),
+            diff.changed.join(", "),
+            started.join(", "),
+            stopped.join(", ")
+        ))
+    }
+
+    /// Whether `service` is meant to be up: started and not deliberately
+    /// stopped or held back.
+    fn is_active(&self, service: &str) -> bool {
+        if self.held.contains_key(service) {
+            return false;
+        }
+        self.awaiting.iter().any(|name| name == service)
+            || self.ready.contains(service)
+            || self
+                .restarts
+                .get(service)
+                .is_some_and(|tracker| tracker.next_at.is_some())
+            || matches!(
+                self.service_state(service),
+                Some(State::Starting | State::Running | State::Restarting | State::Failed)
+            )
+    }
+
+    /// Stop a service that is no longer configured and forget it.
+    fn remove_service(&mut self, service: &str) {
+        if let Some(tx) = self.workers.remove(service) {
+            tx.send(Cmd::Shutdown).ok();
+        }
+        self.restarts.remove(service);
+        self.failure_actions.remove(service);
+        self.held.remove(service);
+        self.awaiting.retain(|name| name != service);
+        self.running.remove(service);
+        self.ready.remove(service);
+        self.clean_exits.remove(service);
+        self.states.remove(service);
+        self.processes.remove(service);
+        self.health.remove(service);
+        self.jobs.remove(service);
+        self.replacing.remove(service);
+        self.added_instances.remove(service);
+        info!("Removed service {service}");
+    }
+
+    /// Hand a changed service to its new worker `tx`.  If it is to come
+    /// back, it starts once the old worker's process is gone.
+    fn replace_worker(&mut self, service: &str, tx: Sender<Cmd>, active: bool) {
+        if let Some(old) = self.workers.insert(service.to_string(), tx) {
+            old.send(Cmd::Shutdown).ok();
+        }
+        info!("Replaced the worker of changed service {service}");
+        if !active {
+            return;
+        }
+        let live = matches!(
+            self.states.get(service),
+            Some(State::Starting | State::Running | State::Stopping | State::Restarting)
+        );
+        if live {
+            let grace = self
+                .definitions
+                .get(service)
+                .and_then(|def| def.stop_timeout_s)
+                .map_or(DEFAULT_STOP_TIMEOUT, Duration::from_secs)
+                + SHUTDOWN_MARGIN;
+            self.replacing
+                .insert(service.to_string(), Instant::now() + grace);
+        } else if !self.awaiting.iter().any(|name| name == service) {
+            self.awaiting.push(service.to_string());
+        }
+    }
+
+    /// The old process of `service` is gone: start the new worker.
+    pub(super) fn resume_replaced(&mut self, service: &str) {
+        if self.replacing.remove(service).is_none() {
+            return;
+        }
+        if !self.awaiting.iter().any(|name| name == service) {
+            self.awaiting.push(service.to_string());
+        }
+        let graph = &self.graph;
+        self.awaiting.sort_by_key(|name| graph.rank(name));
+        self.start_ready();
+    }
+
+    /// Start the new workers whose predecessors outlived their stop
+    /// timeout.
+    pub(super) fn resume_overdue_replacements(&mut self) {
+        let now = Instant::now();
+        let overdue: Vec<String> = self
+            .replacing
+            .iter()
+            .filter(|(_, deadline)| now >= **deadline)
+            .map(|(service, _)| service.clone())
+            .collect();
+        for service in overdue {
+            warn!("{service}: old worker did not report stopped – starting the new one anyway");
+            self.resume_replaced(&service);
+        }
+    }
+
+    /// Keep the category servers whose port and `enabled` flag are
+    /// unchanged, stop the others and start what is missing.  Returns the
+    /// names started and stopped.
+    async fn reconcile_category_servers(
+        &mut self,
+        configs: &[CategoryServerConfig],
+    ) -> (Vec<String>, Vec<String>) {
+        // Servers are only managed here once `start_http_servers` ran.
+        let Some(servers) = self.embedded_servers.take() else {
+            return (Vec::new(), Vec::new());
+        };
+        let (kept, stale): (Vec<EmbeddedServer>, Vec<EmbeddedServer>) =
+            servers.into_iter().partition(|server| {
+                configs
+                    .iter()
+                    .any(|cfg| cfg.enabled && cfg.name == server.name && cfg.port == server.port)
+            });
+        let stopped: Vec<String> = stale.iter().map(|server| server.name.clone()).collect();
+        if !stale.is_empty()
+            && let Err(e) = shutdown_all_servers(stale).await
+        {
+            error!("Error stopping category servers: {e:#}");
+        }
+
+        let missing: Vec<CategoryServerConfig> = configs
+            .iter()
+            .filter(|cfg| cfg.enabled && !kept.iter().any(|server| server.name == cfg.name))
+            .cloned()
+            .collect();
+        let mut servers = kept;
+        let mut started = Vec::new();
+        if !missing.is_empty() {
+            let (tls_cert, tls_key) = crate::config::discover_certificate_paths();
+            match start_all_servers(missing, tls_cert, tls_key).await {
+                Ok(new) => {
+                    started = new.iter().map(|server| server.name.clone()).collect();
+                    servers.extend(new);
+                }
+                Err(e) => error!("Failed to start category servers: {e:#}"),
+            }
+        }
+        self.embedded_servers = Some(servers);
+        (started, stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defs(entries: &[(&str, &str)]) -> HashMap<String, ServiceDefinition> {
+        entries
+            .iter()
+            .map(|(name, command)| {
+                let def: ServiceDefinition =
+                    toml::from_str(&format!("name = \"{name}\"\ncommand = \"{command}\""))
+                        .expect("valid definition");
+                (name.to_string(), def)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diffs_by_definition() {
+        let old = defs(&[("api", "api --port 80"), ("db", "db"), ("cache", "cache")]);
+        let new = defs(&[
+            ("api", "api --port 8080"),
+            ("db", "db"),
+            ("worker", "worker"),
+        ]);
+        assert_eq!(
+            Diff::between(&old, &new, false),
+            Diff {
+                added: vec!["worker".into()],
+                removed: vec!["cache".into()],
+                changed: vec!["api".into()],
+            }
+        );
+        assert_eq!(
+            Diff::between(&old, &new, true).changed,
+            ["api".to_string(), "db".to_string()]
+        );
+        assert_eq!(Diff::between(&new, &new, false), Diff::default());
+    }
+
+    #[test]
+    fn keeps_runtime_instances() {
+        let template: ServiceDefinition =
+            toml::from_str("name = \"agent@\"\ncommand = \"agent %i\"").expect("valid template");
+        let mut templates = Templates::default();
+        templates.insert("agent", template);
+        let instance = templates.instantiate("agent@web").expect("instance");
+
+        let mut old = defs(&[("db", "db")]);
+        old.insert(instance.name.clone(), instance);
+        let added = HashSet::from(["agent@web".to_string(), "gone@x".to_string()]);
+
+        let mut new = defs(&[("db", "db")]);
+        carry_instances(&added, &templates, &mut new);
+        assert_eq!(Diff::between(&old, &new, false), Diff::default());
+
+        // A changed template restarts the instance with its new definition.
+        let changed: ServiceDefinition =
+            toml::from_str("name = \"agent@\"\ncommand = \"agent --v2 %i\"")
+                .expect("valid template");
+        let mut templates = Templates::default();
+        templates.insert("agent", changed);
+        let mut new = defs(&[("db", "db")]);
+        carry_instances(&added, &templates, &mut new);
+        assert_eq!(new["agent@web"].command, "agent --v2 web");
+        assert_eq!(Diff::between(&old, &new, false).changed, ["agent@web"]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Control socket requests, answered inside the manager's event loop.
+
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{Sender, bounded};
+use log::{info, warn};
+
+use crate::api::{Body, Op, ServiceStatus};
+use crate::ipc::Cmd;
+use crate::state_machine::State;
+
+use super::ServiceManager;
+use super::restart::RestartTracker;
+
+/// How long a worker may take to hand over its captured output.
+const TAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl ServiceManager {
+    /// Answer `op` on `reply`.
+    pub(super) fn handle_request(&mut self, op: Op, reply: Sender<Body>) {
+        let body = match op {
+            Op::List => {
+                let mut names: Vec<&String> = self.definitions.keys().collect();
+                names.sort();
+                Body::Services {
+                    services: names.into_iter().map(|name| self.status(name)).collect(),
+                }
+            }
+            Op::Status { service } if self.workers.contains_key(&service) => Body::Service {
+                service: self.status(&service),
+            },
+            Op::Start { service } if self.workers.contains_key(&service) => {
+                self.start_service(&service)
+            }
+            Op::Stop { service } if self.workers.contains_key(&service) => {
+                self.stop_service(&service)
+            }
+            Op::Restart { service } if self.workers.contains_key(&service) => {
+                self.restart_service(&service)
+            }
+            Op::Logs {
+                service,
+                lines,
+                after,
+            } if self.workers.contains_key(&service) => {
+                return self.tail_logs(&service, lines, after, reply);
+            }
+            Op::Status { service }
+            | Op::Start { service }
+            | Op::Stop { service }
+            | Op::Restart { service }
+            | Op::Logs { service, .. } => Body::error(format!("no service '{service}'")),
+            Op::AddInstance { service } => match self.add_instance(&service) {
+                Ok(()) => Body::done(format!("added {service}")),
+                Err(e) => Body::error(format!("{e:#}")),
+            },
+            Op::Events { filter, limit } => Body::Events {
+                events: self.events.recent(&filter, limit),
+            },
+            // The connection turns it into a `Call::Subscribe` of its own.
+            Op::Subscribe { .. } => Body::error("subscriptions are not requests"),
+            // Answered by the event loop, which can wait for the category
+            // servers.
+            Op::Reload => Body::error("reload is not handled here"),
+        };
+        reply.send(body).ok();
+    }
+
+    fn status(&self, service: &str) -> ServiceStatus {
+        let process = self.processes.get(service);
+        ServiceStatus {
+            name: service.to_string(),
+            state: self
+                .service_state(service)
+                .unwrap_or(State::Stopped)
+                .as_str()
+                .to_string(),
+            pid: process.map(|(pid, _)| *pid),
+            uptime_s: process.map(|(_, since)| since.elapsed().as_secs()),
+            restarts: self
+                .restarts
+                .get(service)
+                .map_or(0, RestartTracker::attempts),
+            ready: self.ready.contains(service),
+            healthy: self.health.get(service).copied(),
+        }
+    }
+
+    /// Ask the worker for its captured output.  The answer is passed on
+    /// from a helper thread: a worker busy running a hook must not hold up
+    /// the event loop.
+    fn tail_logs(&self, service: &str, lines: usize, after: Option<u64>, reply: Sender<Body>) {
+        let (tail_tx, tail) = bounded(1);
+        if let Some(tx) = self.workers.get(service) {
+            tx.send(Cmd::TailLogs {
+                lines,
+                reply: tail_tx,
+            })
+            .ok();
+        }
+        let spawned = thread::Builder::new()
+            .name("tail-logs".into())
+            .spawn(move || {
+                let body = match tail.recv_timeout(TAIL_TIMEOUT) {
+                    Ok(mut lines) => {
+                        if let Some(after) = after {
+                            lines.retain(|line| line.seq > after);
+                        }
+                        Body::Logs { lines }
+                    }
+                    Err(_) => Body::error("the service did not answer"),
+                };
+                reply.send(body).ok();
+            });
+        if let Err(e) = spawned {
+            warn!("Cannot pass on the logs of {service}: {e}");
+        }
+    }
+
+    /// Start `service` once its dependencies are ready, giving it a fresh
+    /// restart budget.
+    fn start_service(&mut self, service: &str) -> Body {
+        if let Some(tracker) = self.restarts.get_mut(service) {
+            tracker.reset();
+        }
+        self.held.remove(service);
+        if !self.awaiting.iter().any(|name| name == service) {
+            self.awaiting.push(service.to_string());
+        }
+        let graph = &self.graph;
+        self.awaiting.sort_by_key(|name| graph.rank(name));
+        self.start_ready();
+        if self.awaiting.iter().any(|name| name == service) {
+            info!("{service} requested – waiting for its dependencies");
+            Body::done(format!("{service} starts once its dependencies are ready"))
+        } else {
+            Body::done(format!("starting {service}"))
+        }
+    }
+
+    /// Stop `service` and keep it down: no pending restart, no release
+    /// when a dependency recovers.
+    fn stop_service(&mut self, service: &str) -> Body {
+        self.awaiting.retain(|name| name != service);
+        self.held.remove(service);
+        if let Some(tracker) = self.restarts.get_mut(service) {
+            tracker.next_at = None;
+        }
+        if let Some(tx) = self.workers.get(service) {
+            tx.send(Cmd::Stop).ok();
+            info!("Stopped {service} on request");
+        }
+        Body::done(format!("stopping {service}"))
+    }
+
+    fn restart_service(&mut self, service: &str) -> Body {
+        if let Some(tracker) = self.restarts.get_mut(service) {
+            tracker.reset();
+        }
+        self.held.remove(service);
+        self.awaiting.retain(|name| name != service);
+        if let Some(tx) = self.workers.get(service) {
+            tx.send(Cmd::Restart).ok();
+            info!("Restarting {service} on request");
+        }
+        Body::done(format!("restarting {service}"))
+    }
+}
@@ -0,0 +1,202 @@
+//! Restart bookkeeping for one service: policy, exponential backoff and
+//! crash‑loop detection.
+//!
+//! The delay starts at `restart_delay_s` and doubles with every consecutive
+//! restart up to `restart.max_delay_s`.  More than `restart.burst` restarts
+//! inside `restart.window_s` put the service into `Failed` instead of
+//! restarting it forever; `restart.reset_after_s` of stable uptime clears the
+//! attempt counter again.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::{RestartPolicy, ServiceDefinition};
+
+const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// What the manager should do about a service that went down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Restart after `delay`; this is restart number `attempt`.
+    Restart { delay: Duration, attempt: u32 },
+    /// The policy says to leave the service down.
+    Skip,
+    /// Crash loop: `restarts` within `window` – mark the service failed.
+    GiveUp { restarts: u32, window: Duration },
+}
+
+#[derive(Debug)]
+pub struct RestartTracker {
+    policy: RestartPolicy,
+    base: Duration,
+    max: Duration,
+    burst: u32,
+    window: Duration,
+    reset_after: Duration,
+    attempts: u32,
+    recent: VecDeque<Instant>,
+    started_at: Option<Instant>,
+    /// When the pending restart is due, if one is scheduled.
+    pub next_at: Option<Instant>,
+    failed: bool,
+}
+
+impl RestartTracker {
+    /// Build the tracker for `def`.  `daemon_auto_restart` is
+    /// `ServiceConfig::auto_restart`, used when the service sets no policy.
+    #[must_use]
+    pub fn new(def: &ServiceDefinition, daemon_auto_restart: bool) -> Self {
+        let cfg = def.restart.clone().unwrap_or_default();
+        let policy = cfg.policy.unwrap_or(if def.auto_restart {
+            RestartPolicy::Always
+        } else if daemon_auto_restart {
+            RestartPolicy::OnFailure
+        } else {
+            RestartPolicy::Never
+        });
+        Self {
+            policy,
+            base: def
+                .restart_delay_s
+                .map_or(DEFAULT_BASE_DELAY, Duration::from_secs),
+            max: Duration::from_secs(cfg.max_delay_s),
+            burst: cfg.burst.max(1),
+            window: Duration::from_secs(cfg.window_s),
+            reset_after: Duration::from_secs(cfg.reset_after_s),
+            attempts: 0,
+            recent: VecDeque::new(),
+            started_at: None,
+            next_at: None,
+            failed: false,
+        }
+    }
+
+    /// The service reported running.
+    pub fn on_started(&mut self, now: Instant) {
+        self.started_at = Some(now);
+    }
+
+    /// The service went down; `failure` is false for a clean exit.
+    pub fn on_down(&mut self, failure: bool, now: Instant) -> Decision {
+        if self.failed || self.next_at.is_some() {
+            return Decision::Skip;
+        }
+        let wanted = match self.policy {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failure,
+            RestartPolicy::Never => false,
+        };
+        if !wanted {
+            return Decision::Skip;
+        }
+
+        // A long stable run wipes the slate clean.
+        if self
+            .started_at
+            .take()
+            .is_some_and(|t| now.duration_since(t) >= self.reset_after)
+        {
+            self.attempts = 0;
+            self.recent.clear();
+        }
+
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > self.window)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.burst as usize {
+            self.failed = true;
+            return Decision::GiveUp {
+                restarts: self.recent.len() as u32,
+                window: self.window,
+            };
+        }
+
+        self.attempts = self.attempts.saturating_add(1);
+        self.recent.push_back(now);
+        let delay = self
+            .base
+            .saturating_mul(1 << (self.attempts - 1).min(16))
+            .min(self.max.max(self.base));
+        self.next_at = Some(now + delay);
+        Decision::Restart {
+            delay,
+            attempt: self.attempts,
+        }
+    }
+
+    /// Forget past restarts and any pending one, e.g. when an operator
+    /// starts the service by hand.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.recent.clear();
+        self.next_at = None;
+        self.failed = false;
+    }
+
+    #[must_use]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    #[must_use]
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(extra: &str) -> RestartTracker {
+        let def: ServiceDefinition = toml::from_str(&format!(
+            "name = \"svc\"\ncommand = \"true\"\nrestart_delay_s = 1\n{extra}"
+        ))
+        .expect("valid definition");
+        RestartTracker::new(&def, true)
+    }
+
+    #[test]
+    fn backs_off_and_gives_up_after_burst() {
+        let mut t = tracker("[restart]\nmax_delay_s = 3\nburst = 3\nwindow_s = 60");
+        let now = Instant::now();
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            match t.on_down(true, now) {
+                Decision::Restart { delay, .. } => delays.push(delay.as_secs()),
+                other => panic!("unexpected {other:?}"),
+            }
+            t.next_at = None;
+        }
+        assert_eq!(delays, [1, 2, 3]);
+        assert!(matches!(
+            t.on_down(true, now),
+            Decision::GiveUp { restarts: 3, .. }
+        ));
+        assert!(t.is_failed());
+    }
+
+    #[test]
+    fn policy_and_stable_uptime() {
+        let mut on_failure = tracker("");
+        assert_eq!(on_failure.on_down(false, Instant::now()), Decision::Skip);
+
+        let mut t = tracker("[restart]\npolicy = \"always\"\nreset_after_s = 10");
+        let start = Instant::now();
+        t.on_down(false, start);
+        t.next_at = None;
+        t.on_started(start);
+        let later = start + Duration::from_secs(11);
+        assert_eq!(
+            t.on_down(false, later),
+            Decision::Restart {
+                delay: Duration::from_secs(1),
+                attempt: 1
+            }
+        );
+    }
+}
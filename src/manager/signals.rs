@@ -0,0 +1,128 @@
+//! Unix signals as events of the manager's loop (self-pipe).
+//!
+//! The handler only writes the signal number to a non-blocking pipe; a
+//! reader thread turns every byte into a message on a channel the loop
+//! selects on.  Each delivery is its own message, so two quick SIGHUPs are
+//! two reloads and a SIGTERM is acted on at once instead of at the next
+//! poll.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender, bounded};
+use log::warn;
+use nix::errno::Errno;
+use nix::fcntl::{FcntlArg, FdFlag, OFlag, fcntl};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// Signals the manager acts on.
+const HANDLED: [Signal; 3] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP];
+
+/// Signals queued for the loop before the reader waits for it.
+const QUEUE_BOUND: usize = 16;
+
+/// Write end of the self-pipe, -1 until installed.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handler(sig: libc::c_int) {
+    let fd = PIPE.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    // Signal numbers fit in a byte.
+    let byte = sig as u8;
+    let errno = Errno::last_raw();
+    // SAFETY: write(2) is async-signal-safe and `byte` outlives the call.
+    // A full pipe drops the signal rather than blocking the handler.
+    unsafe {
+        libc::write(fd, (&raw const byte).cast(), 1);
+    }
+    Errno::set_raw(errno);
+}
+
+/// Install the handlers for SIGINT, SIGTERM and SIGHUP.  Every signal
+/// received from now on arrives on the returned channel.
+pub fn install_signal_handlers() -> Result<Receiver<Signal>> {
+    let (read_end, write_end) = nix::unistd::pipe().context("Failed to create the signal pipe")?;
+    for fd in [&read_end, &write_end] {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .context("Failed to set up the signal pipe")?;
+    }
+    fcntl(&write_end, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+        .context("Failed to set up the signal pipe")?;
+
+    let (tx, rx) = bounded(QUEUE_BOUND);
+    let pipe = File::from(read_end);
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || forward(pipe, &tx))
+        .context("Failed to spawn the signal thread")?;
+    // The write end stays open for the life of the process.
+    PIPE.store(write_end.into_raw_fd(), Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for sig in HANDLED {
+        // SAFETY: the handler only calls async-signal-safe functions.
+        unsafe { signal::sigaction(sig, &action) }.with_context(|| {
+            format!("Failed to register {sig} handler. Signal handling is required for daemon operation.")
+        })?;
+    }
+    Ok(rx)
+}
+
+/// Pass each signal number read from `pipe` on to `tx` until the receiver
+/// is gone.
+fn forward(mut pipe: File, tx: &Sender<Signal>) {
+    let mut buf = [0u8; 16];
+    loop {
+        let read = match pipe.read(&mut buf) {
+            Ok(0) => return,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Signal pipe unreadable, signals are no longer handled: {e}");
+                return;
+            }
+        };
+        for &byte in &buf[..read] {
+            match Signal::try_from(i32::from(byte)) {
+                Ok(sig) => {
+                    if tx.send(sig).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("Ignoring unknown signal number {byte}: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn every_signal_is_delivered() {
+        let signals = install_signal_handlers().expect("handlers installed");
+        for _ in 0..3 {
+            signal::raise(Signal::SIGHUP).expect("raised");
+        }
+        for _ in 0..3 {
+            assert_eq!(
+                signals.recv_timeout(Duration::from_secs(5)),
+                Ok(Signal::SIGHUP)
+            );
+        }
+        assert!(signals.try_recv().is_err());
+    }
+}
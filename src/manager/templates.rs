@@ -0,0 +1,250 @@
+//! Service templates: `<prefix>@.toml` in `services_dir` describes any
+//! number of instances named `<prefix>@<instance>`.
+//!
+//! The template's own `name` is ignored.  In `description`, `command`,
+//! `working_dir`, the `env_vars` values, the hook commands and every path
+//! or address – `env_files`, `watch_dirs`, `ephemeral_dir`, the memfs
+//! `mount_name`, the sandbox's `read_write_paths`, the socket activation
+//! `listen` addresses, the health check's `target` and `on_failure`
+//! actions and the readiness `host` – `%i` stands for the instance, `%p`
+//! for the prefix, `%n` for the full instance name and `%%` for a literal
+//! `%`.  Instances of one template thus get their own scratch space and
+//! sockets.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use log::warn;
+use thiserror::Error;
+
+use crate::config::ServiceDefinition;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("'{0}' is not an instance name (expected template@instance)")]
+    NotInstance(String),
+
+    #[error("no template '{0}@' in services_dir")]
+    Unknown(String),
+
+    #[error("invalid instance '{0}' (letters, digits, '-', '_', '.' and ':' only)")]
+    InvalidInstance(String),
+}
+
+/// Templates loaded from `services_dir`, keyed by prefix.
+#[derive(Debug, Default)]
+pub struct Templates {
+    by_prefix: HashMap<String, ServiceDefinition>,
+}
+
+impl Templates {
+    pub fn insert(&mut self, prefix: &str, template: ServiceDefinition) {
+        if self
+            .by_prefix
+            .insert(prefix.to_string(), template)
+            .is_some()
+        {
+            warn!("Template '{prefix}@' defined more than once – last definition wins");
+        }
+    }
+
+    /// The definition of instance `name` (`<prefix>@<instance>`).
+    pub fn instantiate(&self, name: &str) -> Result<ServiceDefinition, TemplateError> {
+        let (prefix, instance) = name
+            .split_once('@')
+            .ok_or_else(|| TemplateError::NotInstance(name.to_string()))?;
+        let valid = !instance.is_empty()
+            && !instance.starts_with('.')
+            && instance
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        if !valid {
+            return Err(TemplateError::InvalidInstance(instance.to_string()));
+        }
+        let template = self
+            .by_prefix
+            .get(prefix)
+            .ok_or_else(|| TemplateError::Unknown(prefix.to_string()))?;
+
+        let expand = |raw: &str| expand(raw, prefix, instance);
+        let mut def = template.clone();
+        def.name = name.to_string();
+        def.description = def.description.as_deref().map(expand);
+        def.command = expand(&def.command);
+        def.working_dir = def.working_dir.as_deref().map(expand);
+        for value in def.env_vars.values_mut() {
+            *value = expand(value);
+        }
+        for path in def.env_files.iter_mut().chain(def.watch_dirs.iter_mut()) {
+            *path = expand(path);
+        }
+        def.ephemeral_dir = def.ephemeral_dir.as_deref().map(expand);
+        if let Some(memfs) = def.memfs.as_mut() {
+            memfs.mount_name = expand(&memfs.mount_name);
+        }
+        if let Some(sandbox) = def.sandbox.as_mut() {
+            for path in &mut sandbox.read_write_paths {
+                *path = expand(path);
+            }
+        }
+        if let Some(activation) = def.socket_activation.as_mut() {
+            for listen in &mut activation.listen {
+                *listen = expand(listen);
+            }
+        }
+        if let Some(health) = def.health_check.as_mut() {
+            health.target = expand(&health.target);
+            for action in &mut health.on_failure {
+                *action = expand(action);
+            }
+        }
+        if let Some(readiness) = def.readiness.as_mut() {
+            readiness.host = readiness.host.as_deref().map(expand);
+        }
+        if let Some(hooks) = def.hooks.as_mut() {
+            let stages = [
+                &mut hooks.pre_start,
+                &mut hooks.post_start,
+                &mut hooks.pre_stop,
+                &mut hooks.post_stop,
+            ];
+            for hook in stages.into_iter().flatten() {
+                hook.command = expand(&hook.command);
+            }
+        }
+        Ok(def)
+    }
+}
+
+/// The prefix of a template file `<prefix>@.toml`, if `path` is one.
+pub fn template_prefix(path: &Path) -> Option<&str> {
+    path.file_stem()?
+        .to_str()?
+        .strip_suffix('@')
+        .filter(|prefix| !prefix.is_empty())
+}
+
+fn expand(raw: &str, prefix: &str, instance: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('i') => out.push_str(instance),
+            Some('p') => out.push_str(prefix),
+            Some('n') => {
+                out.push_str(prefix);
+                out.push('@');
+                out.push_str(instance);
+            }
+            Some('%') => out.push('%'),
+            // Anything else stays as written.
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> Templates {
+        let template = toml::from_str(
+            "name = \"agent@\"\n\
+             command = \"agent --project %i --log /var/log/%n.log --pct 100%%\"\n\
+             working_dir = \"/srv/%i\"\n\
+             env_vars = { PROJECT = \"%i\", ROLE = \"%p\" }",
+        )
+        .expect("valid template");
+        let mut templates = Templates::default();
+        templates.insert("agent", template);
+        templates
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let def = templates().instantiate("agent@web").expect("instance");
+        assert_eq!(def.name, "agent@web");
+        assert_eq!(
+            def.command,
+            "agent --project web --log /var/log/agent@web.log --pct 100%"
+        );
+        assert_eq!(def.working_dir.as_deref(), Some("/srv/web"));
+        assert_eq!(def.env_vars["PROJECT"], "web");
+        assert_eq!(def.env_vars["ROLE"], "agent");
+        assert_eq!(
+            template_prefix(Path::new("/etc/x/agent@.toml")),
+            Some("agent")
+        );
+        assert_eq!(template_prefix(Path::new("/etc/x/agent.toml")), None);
+    }
+
+    #[test]
+    fn instances_do_not_share_resources() {
+        let template = toml::from_str(
+            "name = \"worker@\"\n\
+             command = \"worker\"\n\
+             ephemeral_dir = \"/var/tmp/%n\"\n\
+             memfs = { size_mb = 64, mount_name = \"%p-%i\" }\n\
+             socket_activation = { listen = [\"/run/worker/%i.sock\"] }\n\
+             [health_check]\n\
+             check_type = \"http\"\n\
+             target = \"http://127.0.0.1/%i/health\"\n\
+             interval_secs = 30\n\
+             timeout_secs = 5\n\
+             retries = 3",
+        )
+        .expect("valid template");
+        let mut templates = Templates::default();
+        templates.insert("worker", template);
+
+        let a = templates.instantiate("worker@a").expect("instance");
+        let b = templates.instantiate("worker@b").expect("instance");
+        assert_eq!(a.ephemeral_dir.as_deref(), Some("/var/tmp/worker@a"));
+        assert_eq!(b.ephemeral_dir.as_deref(), Some("/var/tmp/worker@b"));
+        assert_eq!(a.memfs.expect("memfs").mount_name, "worker-a");
+        assert_eq!(b.memfs.expect("memfs").mount_name, "worker-b");
+        assert_eq!(
+            a.socket_activation.expect("activation").listen,
+            ["/run/worker/a.sock"]
+        );
+        assert_eq!(
+            b.socket_activation.expect("activation").listen,
+            ["/run/worker/b.sock"]
+        );
+        assert_eq!(
+            a.health_check.expect("health").target,
+            "http://127.0.0.1/a/health"
+        );
+        assert_eq!(
+            b.health_check.expect("health").target,
+            "http://127.0.0.1/b/health"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_instances() {
+        let templates = templates();
+        assert_eq!(
+            templates.instantiate("agent").unwrap_err(),
+            TemplateError::NotInstance("agent".into())
+        );
+        assert_eq!(
+            templates.instantiate("db@main").unwrap_err(),
+            TemplateError::Unknown("db".into())
+        );
+        assert_eq!(
+            templates.instantiate("agent@../etc").unwrap_err(),
+            TemplateError::InvalidInstance("../etc".into())
+        );
+        assert!(templates.instantiate("agent@").is_err());
+    }
+}
@@ -1,19 +1,58 @@
 mod autoconfig;
 
+pub mod activation;
+pub mod cgroup;
+pub mod credentials;
 pub mod embedded_servers;
+pub mod ephemeral;
+pub mod health;
+pub mod hooks;
+pub mod logs;
+pub mod memfs;
+pub mod process;
+pub mod readiness;
+pub mod sandbox;
+pub mod schedule;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
+pub mod secrets;
+pub mod watch;
 
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
-use chrono::Utc;
-use crossbeam_channel::{Receiver, Sender, bounded, select, tick};
+use anyhow::Result;
+use chrono::{Local, Utc};
+use crossbeam_channel::{Receiver, Sender, at, bounded, never, select, tick};
 use log::{error, info, warn};
+use nix::sys::signal::Signal;
+use nix::unistd::Uid;
 use thiserror::Error;
 
-use crate::config::ServiceDefinition;
-use crate::ipc::{Cmd, Evt};
+use crate::config::{ServiceConfig, ServiceDefinition};
+use crate::ipc::{Cmd, Evt, ExitInfo};
+use crate::lifecycle::Lifecycle;
+use crate::state_machine::{Action, Event, State};
+use activation::Listeners;
+use cgroup::ServiceCgroup;
+use credentials::{CredentialError, Credentials};
+use health::{HealthProbe, Verdict};
+use hooks::{HookEnv, Stage};
+use logs::{LineWatch, LogCapture};
+use memfs::MemFs;
+use readiness::{NotifySocket, Readiness};
+use sandbox::Sandbox;
+use schedule::Schedule;
+
+/// How often a worker checks whether its log files are due for rotation.
+const ROTATE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often services with `resources` report their cgroup usage.
+const USAGE_REPORT_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Service worker errors
 #[derive(Error, Debug)]
@@ -31,29 +70,169 @@ pub enum ServiceError {
     ChannelSend(#[from] crossbeam_channel::SendError<crate::ipc::Evt>),
 }
 
+/// Daemon‑wide settings every worker inherits from `ServiceConfig`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerDefaults {
+    pub log_dir: Option<PathBuf>,
+    pub default_user: Option<String>,
+    pub default_group: Option<String>,
+}
+
+impl From<&ServiceConfig> for WorkerDefaults {
+    fn from(cfg: &ServiceConfig) -> Self {
+        Self {
+            log_dir: cfg.log_dir.as_ref().map(PathBuf::from),
+            default_user: cfg.default_user.clone(),
+            default_group: cfg.default_group.clone(),
+        }
+    }
+}
+
+/// What a successful spawn leaves the worker to own.
+struct Launched {
+    child: Child,
+    memfs: Option<MemFs>,
+    cgroup: Option<ServiceCgroup>,
+    readiness: Readiness,
+    notify: Option<NotifySocket>,
+    /// Pings once the child reports ready (`notify` and `log-line`).
+    ready: Receiver<()>,
+    hook_env: HookEnv,
+}
+
 pub struct ServiceWorker {
     name: &'static str,
     rx: Receiver<Cmd>,
-    tx: Sender<Cmd>,
     bus: Sender<Evt>,
     def: ServiceDefinition,
+    defaults: WorkerDefaults,
+    logs: LogCapture,
+    health: Option<HealthProbe>,
+    lifecycle: Lifecycle,
+    child: Option<Child>,
+    /// RAM‑backed scratch area of the current run.
+    memfs: Option<MemFs>,
+    /// cgroup holding the current run, if `resources` are set.
+    cgroup: Option<ServiceCgroup>,
+    /// OOM kills already reported for the current run.
+    oom_kills: u64,
+    /// When `child` was spawned.
+    started: Instant,
+    /// Pids of exited children, sent by their waiter threads.
+    exit_tx: Sender<u32>,
+    exit_rx: Receiver<u32>,
+    /// Pings from the `watch_dirs` watcher; never fires without one.
+    changes: Receiver<()>,
+    /// When debounced `watch_dirs` changes trigger a restart.
+    reload_at: Option<Instant>,
+    /// Failure count and reason reported by the next `NotifyUnhealthy`.
+    failure: Option<(u32, String)>,
+    /// When a `scheduled` service runs.
+    schedule: Option<Schedule>,
+    /// When its next run is due; `None` while it is stopped.
+    next_run: Option<Instant>,
+    /// Readiness check of the current run.
+    readiness: Readiness,
+    notify: Option<NotifySocket>,
+    ready: Receiver<()>,
+    /// Deadline for the current run to become ready; `None` once it is.
+    ready_by: Option<Instant>,
+    /// Sockets of a socket‑activated service, bound while it is started.
+    listeners: Option<Listeners>,
+    /// Whether the next connection starts the service.
+    waiting: bool,
+    /// When the current run last had a connection.
+    last_active: Instant,
+    /// What the hooks of the current run inherit; `None` between runs.
+    hook_env: Option<HookEnv>,
 }
 
 impl ServiceWorker {
-    pub fn spawn(def: ServiceDefinition, bus: Sender<Evt>) -> Result<Sender<Cmd>, ServiceError> {
+    pub fn spawn(
+        def: ServiceDefinition,
+        bus: Sender<Evt>,
+        defaults: &WorkerDefaults,
+    ) -> Result<Sender<Cmd>, ServiceError> {
         let (tx, rx) = bounded::<Cmd>(16);
         let name: &'static str = Box::leak(def.name.clone().into_boxed_str());
-        let tx_clone = tx.clone();
+        let defaults = defaults.clone();
 
         thread::Builder::new()
             .name(format!("svc-{name}"))
             .spawn(move || {
+                let health = match def.health_check.as_ref().map(HealthProbe::new).transpose() {
+                    Ok(probe) => probe,
+                    Err(e) => {
+                        error!("{name}: invalid health check: {e}");
+                        bus.send(Evt::Fatal {
+                            service: name.to_string(),
+                            msg: "invalid health check configuration",
+                            ts: Utc::now(),
+                        })
+                        .ok();
+                        None
+                    }
+                };
+                let schedule = if def.service_type.as_deref() == Some("scheduled") {
+                    let parsed = match def.schedule.as_deref() {
+                        Some(raw) => Schedule::parse(raw).map_err(|e| e.to_string()),
+                        None => Err("no schedule set".to_string()),
+                    };
+                    match parsed {
+                        Ok(schedule) => Some(schedule),
+                        Err(e) => {
+                            error!("{name}: invalid schedule: {e}");
+                            bus.send(Evt::Fatal {
+                                service: name.to_string(),
+                                msg: "invalid schedule",
+                                ts: Utc::now(),
+                            })
+                            .ok();
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                let (exit_tx, exit_rx) = bounded::<u32>(4);
+                let changes = if def.watch_dirs.is_empty() {
+                    never()
+                } else {
+                    let dirs: Vec<PathBuf> = def.watch_dirs.iter().map(PathBuf::from).collect();
+                    watch::watch(name, &dirs).unwrap_or_else(|e| {
+                        warn!("{name}: cannot watch {:?}: {e}", def.watch_dirs);
+                        never()
+                    })
+                };
                 let mut worker = ServiceWorker {
                     name,
                     rx,
-                    tx: tx_clone,
                     bus,
+                    logs: LogCapture::new(name, defaults.log_dir.as_deref()),
                     def,
+                    defaults,
+                    health,
+                    lifecycle: Lifecycle::new(name),
+                    child: None,
+                    memfs: None,
+                    cgroup: None,
+                    oom_kills: 0,
+                    started: Instant::now(),
+                    exit_tx,
+                    exit_rx,
+                    changes,
+                    reload_at: None,
+                    failure: None,
+                    schedule,
+                    next_run: None,
+                    readiness: Readiness::Exec,
+                    notify: None,
+                    ready: never(),
+                    ready_by: None,
+                    listeners: None,
+                    waiting: false,
+                    last_active: Instant::now(),
+                    hook_env: None,
                 };
                 if let Err(e) = worker.run() {
                     error!("Worker {} crashed: {:#}", worker.name, e);
@@ -68,86 +247,756 @@ impl ServiceWorker {
     }
 
     fn run(&mut self) -> Result<()> {
-        let health_tick = tick(Duration::from_secs(60));
-        let rotate_tick = tick(Duration::from_secs(3600));
-        let mut child: Option<Child> = None;
+        let health_tick = tick(
+            self.health
+                .as_ref()
+                .map_or(health::DEFAULT_INTERVAL, HealthProbe::interval),
+        );
+        let rotate_tick = tick(ROTATE_CHECK_INTERVAL);
+        let usage_tick = if self.def.resources.is_some() {
+            tick(USAGE_REPORT_INTERVAL)
+        } else {
+            never()
+        };
+        let tcp_tick = tick(readiness::TCP_POLL);
+        let idle_tick = if self.idle_timeout().is_some() {
+            tick(activation::IDLE_CHECK)
+        } else {
+            never()
+        };
 
         loop {
+            let reload = self.reload_at.map_or_else(never, at);
+            let due = self.next_run.map_or_else(never, at);
+            let (ready, ready_poll, ready_timeout) = match self.ready_by {
+                Some(deadline) => (
+                    self.ready.clone(),
+                    if matches!(self.readiness, Readiness::TcpPort { .. }) {
+                        tcp_tick.clone()
+                    } else {
+                        never()
+                    },
+                    at(deadline),
+                ),
+                None => (never(), never(), never()),
+            };
+            let incoming = match &self.listeners {
+                Some(listeners) if self.waiting => listeners.incoming().clone(),
+                _ => never(),
+            };
             select! {
                 recv(self.rx) -> msg => match msg? {
-                    Cmd::Start    => self.start(&mut child)?,
-                    Cmd::Stop     => self.stop(&mut child)?,
-                    Cmd::Restart  => { self.stop(&mut child)?; self.start(&mut child)?; },
-                    Cmd::Shutdown => { self.stop(&mut child)?; break; },
-                    Cmd::TickHealth   => self.health_check(&mut child)?,
+                    Cmd::Start    => self.start()?,
+                    Cmd::Stop     => self.stop()?,
+                    Cmd::Restart  => self.handle(Event::CmdRestart)?,
+                    Cmd::Shutdown => { self.handle(Event::CmdStop)?; break; },
                     Cmd::TickLogRotate=> self.rotate_logs()?,
+                    Cmd::TailLogs { lines, reply } => { reply.send(self.logs.tail(lines)).ok(); },
+                },
+                recv(self.exit_rx) -> _ => { self.reap()?; },
+                recv(self.changes) -> _ => self.reload_at = Some(Instant::now() + watch::DEBOUNCE),
+                recv(reload) -> _ => self.reload()?,
+                recv(due) -> _ => self.run_scheduled()?,
+                recv(ready) -> msg => match msg {
+                    Ok(()) => self.became_ready()?,
+                    // Output closed or socket gone: only the timeout is left.
+                    Err(_) => self.ready = never(),
                 },
-                recv(health_tick) -> _ => self.health_check(&mut child)?,
+                recv(ready_poll) -> _ => self.poll_ready()?,
+                recv(ready_timeout) -> _ => self.not_ready()?,
+                recv(incoming) -> _ => self.activate()?,
+                recv(idle_tick) -> _ => self.check_idle()?,
+                recv(health_tick) -> _ => self.health_check()?,
                 recv(rotate_tick) -> _ => self.rotate_logs()?,
+                recv(usage_tick) -> _ => self.report_usage()?,
             }
         }
         Ok(())
     }
 
-    fn start(&self, child: &mut Option<Child>) -> Result<()> {
-        if child.is_some() {
-            warn!("{} already running", self.name);
+    /// Run the service – or, for a `scheduled` one, wait for its next slot
+    /// and for a socket‑activated one for the first connection.
+    fn start(&mut self) -> Result<()> {
+        if self.def.service_type.as_deref() == Some("scheduled") {
+            self.arm()
+        } else if self.def.socket_activated() {
+            self.listen()
+        } else {
+            self.handle(Event::CmdStart)
+        }
+    }
+
+    /// Stop the service; a scheduled one no longer runs, a socket‑activated
+    /// one closes its sockets.
+    fn stop(&mut self) -> Result<()> {
+        self.next_run = None;
+        self.listeners = None;
+        self.waiting = false;
+        self.handle(Event::CmdStop)
+    }
+
+    /// Bind the sockets unless they are already, and wait for a connection
+    /// unless the service is running.
+    fn listen(&mut self) -> Result<()> {
+        let Some(cfg) = &self.def.socket_activation else {
+            return Ok(());
+        };
+        if self.listeners.is_none() {
+            match Listeners::bind(self.name, cfg) {
+                Ok(listeners) => self.listeners = Some(listeners),
+                Err(e) => {
+                    error!("{}: {e}", self.name);
+                    self.bus.send(Evt::Fatal {
+                        service: self.name.to_string(),
+                        msg: "cannot bind activation sockets",
+                        ts: Utc::now(),
+                    })?;
+                    return Ok(());
+                }
+            }
+        }
+        if self.child.is_some() {
+            return Ok(());
+        }
+        info!("{}: listening on {}", self.name, cfg.listen.join(", "));
+        self.bus.send(Evt::Listening {
+            service: self.name.to_string(),
+            listen: cfg.listen.clone(),
+            ts: Utc::now(),
+        })?;
+        self.wait_for_connection();
+        Ok(())
+    }
+
+    /// Start the service on the next connection to its sockets.
+    fn wait_for_connection(&mut self) {
+        if let Some(listeners) = &self.listeners {
+            self.waiting = true;
+            listeners.arm();
+        }
+    }
+
+    /// A connection is waiting on a socket of the stopped service.
+    fn activate(&mut self) -> Result<()> {
+        self.waiting = false;
+        if self.child.is_some() {
+            return Ok(());
+        }
+        info!("{}: connection waiting – starting", self.name);
+        self.handle(Event::CmdStart)
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        self.def
+            .socket_activation
+            .as_ref()
+            .and_then(|cfg| cfg.idle_timeout_s)
+            .map(Duration::from_secs)
+    }
+
+    /// Stop a socket‑activated service that has had no connection for
+    /// `idle_timeout_s`; the next one starts it again.
+    fn check_idle(&mut self) -> Result<()> {
+        let (Some(listeners), Some(timeout)) = (&self.listeners, self.idle_timeout()) else {
+            return Ok(());
+        };
+        if self.child.is_none() || !self.lifecycle.is_running() {
+            return Ok(());
+        }
+        if listeners.busy() {
+            self.last_active = Instant::now();
             return Ok(());
         }
+        if self.last_active.elapsed() < timeout {
+            return Ok(());
+        }
+        info!(
+            "{}: idle for {timeout:?} – stopping until the next connection",
+            self.name
+        );
+        self.handle(Event::CmdStop)?;
+        self.wait_for_connection();
+        Ok(())
+    }
+
+    /// Set the timer for the next scheduled run and announce it.
+    fn arm(&mut self) -> Result<()> {
+        self.next_run = None;
+        let Some(schedule) = &self.schedule else {
+            warn!("{}: no valid schedule – not started", self.name);
+            return Ok(());
+        };
+        let now = Local::now();
+        let Some(next) = schedule.next_after(now) else {
+            warn!("{}: schedule never fires again", self.name);
+            return Ok(());
+        };
+        self.next_run = Some(Instant::now() + (next - now).to_std().unwrap_or_default());
+        info!("{}: next run at {next}", self.name);
+        self.bus.send(Evt::Scheduled {
+            service: self.name.to_string(),
+            next_run: next.with_timezone(&Utc),
+            ts: Utc::now(),
+        })?;
+        Ok(())
+    }
+
+    /// A scheduled run is due.  Runs never overlap: one still going when
+    /// the next is due skips that slot.
+    fn run_scheduled(&mut self) -> Result<()> {
+        self.arm()?;
+        if self.child.is_some() {
+            warn!(
+                "{}: previous run still going – skipping this one",
+                self.name
+            );
+            return Ok(());
+        }
+        self.handle(Event::CmdStart)
+    }
+
+    /// Feed `event` to the lifecycle, publish the new state if it changed and
+    /// carry out whatever the transition asks for.
+    fn handle(&mut self, event: Event) -> Result<()> {
+        let before = self.lifecycle.state();
+        let action = self.lifecycle.step(event);
+        let state = self.lifecycle.state();
+        if state != before {
+            self.bus.send(Evt::State {
+                service: self.name.to_string(),
+                kind: state.as_str(),
+                ts: Utc::now(),
+                pid: self.child.as_ref().map(Child::id),
+            })?;
+        }
+        match action {
+            Action::SpawnProcess => self.spawn_process(),
+            Action::KillProcess => self.kill_process(),
+            Action::NotifyHealthy => {
+                info!("{} is up", self.name);
+                Ok(())
+            }
+            Action::NotifyUnhealthy => self.notify_unhealthy(),
+            Action::Noop => Ok(()),
+        }
+    }
+
+    fn spawn_process(&mut self) -> Result<()> {
+        match self.launch() {
+            Ok(Launched {
+                child,
+                memfs,
+                cgroup,
+                readiness,
+                notify,
+                ready,
+                hook_env,
+            }) => {
+                let pid = child.id();
+                info!("{} started (pid {pid})", self.name);
+                if let Err(e) = process::watch_exit(pid, self.exit_tx.clone()) {
+                    warn!(
+                        "{}: no exit watcher ({e}) – exits surface on the next health tick",
+                        self.name
+                    );
+                }
+                self.started = Instant::now();
+                self.last_active = self.started;
+                self.child = Some(child);
+                self.memfs = memfs;
+                self.cgroup = cgroup;
+                self.oom_kills = 0;
+                self.notify = notify;
+                self.ready = ready;
+                self.readiness = readiness;
+                self.hook_env = Some(hook_env);
+                if matches!(self.readiness, Readiness::Exec) {
+                    return self.started();
+                }
+                let timeout = self.ready_timeout();
+                info!(
+                    "{}: waiting up to {timeout:?} for readiness ({:?})",
+                    self.name, self.readiness
+                );
+                self.ready_by = Some(Instant::now() + timeout);
+                Ok(())
+            }
+            Err(reason) => {
+                error!("{}: {reason}", self.name);
+                self.failure = Some((1, reason));
+                self.handle(Event::StartErr)
+            }
+        }
+    }
+
+    fn launch(&self) -> Result<Launched, String> {
+        let creds = match self.credentials() {
+            Ok(creds) => creds,
+            Err(e) => {
+                self.bus
+                    .send(Evt::Fatal {
+                        service: self.name.to_string(),
+                        msg: e.fatal_msg(),
+                        ts: Utc::now(),
+                    })
+                    .ok();
+                return Err(e.to_string());
+            }
+        };
+        if let Some(dir) = &self.def.working_dir {
+            match fs::metadata(dir) {
+                Ok(meta) if meta.is_dir() => {}
+                Ok(_) => return Err(format!("working_dir '{dir}' is not a directory")),
+                Err(e) => return Err(format!("working_dir '{dir}': {e}")),
+            }
+        }
+        let owner = creds
+            .as_ref()
+            .map(|c| (c.user.as_ref().map(|u| u.uid), c.gid));
+        let ephemeral_dir = self.ephemeral_dir()?;
+        if let Some(dir) = &ephemeral_dir {
+            ephemeral::prepare(dir, owner)
+                .map_err(|e| format!("ephemeral_dir '{}': {e}", dir.display()))?;
+        }
+        let memfs = self
+            .def
+            .memfs
+            .as_ref()
+            .map(|cfg| MemFs::create(self.name, cfg, owner))
+            .transpose()
+            .map_err(|e| format!("memfs: {e}"))?;
+        let sandbox = self
+            .def
+            .sandbox
+            .as_ref()
+            .map(|cfg| {
+                let scratch: Vec<&Path> = ephemeral_dir
+                    .as_deref()
+                    .into_iter()
+                    .chain(memfs.as_ref().map(MemFs::path))
+                    .collect();
+                Sandbox::prepare(cfg, &scratch)
+            })
+            .transpose()
+            .map_err(|e| format!("sandbox: {e}"))?;
+
+        let command = match &self.listeners {
+            Some(_) => activation::exec_line(&self.def.command),
+            None => self.def.command.clone(),
+        };
         let mut cmd = Command::new("sh");
         cmd.arg("-c")
-            .arg(&self.def.command)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+            .arg(command)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Own process group, so stop reaches everything the shell forks.
+            .process_group(0);
         if let Some(dir) = &self.def.working_dir {
             cmd.current_dir(dir);
         }
-        let spawned = cmd.spawn().context("spawn")?;
-        let pid = spawned.id();
-        *child = Some(spawned);
-        self.bus.send(Evt::State {
-            service: self.name.to_string(),
-            kind: "running",
-            ts: Utc::now(),
-            pid: Some(pid),
-        })?;
-        info!("{} started (pid {})", self.name, pid);
+        // Hooks run in order.  Join the cgroup and enter the namespaces
+        // while the child still has the privileges to; the seccomp filter
+        // goes last, as it may refuse what the others need.
+        let cgroup = match &self.def.resources {
+            Some(cfg) => match ServiceCgroup::create(self.name, cfg) {
+                Ok(cgroup) => Some(cgroup),
+                // Starting unconfined would silently drop the limits.
+                Err(e) if cfg.sets_limits() => return Err(format!("resources: {e}")),
+                Err(e) => {
+                    warn!("{}: no resource accounting: {e}", self.name);
+                    None
+                }
+            },
+            None => None,
+        };
+        if let Some(cgroup) = &cgroup {
+            cgroup.apply(&mut cmd);
+        }
+        if let Some(sandbox) = &sandbox {
+            sandbox.confine(&mut cmd);
+        }
+        if let Some(creds) = &creds {
+            creds.apply(&mut cmd);
+        }
+        if let Some(listeners) = &self.listeners {
+            listeners.pass(self.name, &mut cmd);
+        }
+        // The seccomp filter goes last: hooks registered after it would run
+        // under it.
+        if let Some(sandbox) = &sandbox {
+            sandbox.restrict(&mut cmd);
+        }
+        let mut vars = secrets::resolve(&self.def).map_err(|e| format!("environment: {e}"))?;
+        if let Some(dir) = &ephemeral_dir {
+            vars.insert(ephemeral::ENV_VAR.into(), dir.display().to_string());
+        }
+        if let Some(memfs) = &memfs {
+            vars.insert(memfs::ENV_VAR.into(), memfs.path().display().to_string());
+        }
+        cmd.envs(&vars);
+        let hook_env = HookEnv {
+            vars,
+            working_dir: self.def.working_dir.clone(),
+            creds,
+        };
+        let readiness = self
+            .def
+            .readiness
+            .as_ref()
+            .map_or(Ok(Readiness::Exec), Readiness::new)?;
+        let (notify, ready, watch) = match &readiness {
+            Readiness::Notify => {
+                let (socket, ready) = NotifySocket::bind(self.name, owner)
+                    .map_err(|e| format!("cannot create {}: {e}", readiness::NOTIFY_ENV))?;
+                cmd.env(readiness::NOTIFY_ENV, socket.path());
+                (Some(socket), ready, None)
+            }
+            Readiness::LogLine(pattern) => {
+                let (notify, ready) = bounded(1);
+                let watch = LineWatch {
+                    pattern: pattern.clone(),
+                    notify,
+                };
+                (None, ready, Some(watch))
+            }
+            Readiness::Exec | Readiness::TcpPort { .. } => (None, never(), None),
+        };
+        hooks::run(
+            self.name,
+            Stage::PreStart,
+            self.def.hooks.as_ref(),
+            &hook_env,
+            &self.logs,
+        )
+        .map_err(|e| e.to_string())?;
+        let mut spawned = cmd.spawn().map_err(|e| format!("spawn failed: {e}"))?;
+        self.logs.attach(&mut spawned, watch);
+        Ok(Launched {
+            child: spawned,
+            memfs,
+            cgroup,
+            readiness,
+            notify,
+            ready,
+            hook_env,
+        })
+    }
+
+    fn ephemeral_dir(&self) -> Result<Option<PathBuf>, String> {
+        self.def
+            .ephemeral_dir
+            .as_deref()
+            .map(|raw| ephemeral::resolve(raw, self.def.working_dir.as_deref()))
+            .transpose()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Clean up per‑run resources once the process is gone.
+    fn end_run(&mut self) -> Result<()> {
+        if self.hook_env.is_some() {
+            // Stop hooks only warn.
+            self.run_hooks(Stage::PostStop).ok();
+            self.hook_env = None;
+        }
+        // Catch an OOM kill that took the process down.
+        self.report_usage()?;
+        // Dropping them unmounts and removes them.
+        self.memfs = None;
+        self.cgroup = None;
+        self.notify = None;
+        self.ready = never();
+        self.ready_by = None;
+        if let Ok(Some(dir)) = self.ephemeral_dir()
+            && let Err(e) = ephemeral::wipe(&dir)
+        {
+            warn!(
+                "{}: cannot remove ephemeral_dir {}: {e}",
+                self.name,
+                dir.display()
+            );
+        }
         Ok(())
     }
 
-    fn stop(&self, child: &mut Option<Child>) -> Result<()> {
-        if let Some(mut ch) = child.take() {
+    /// Identity to drop to before exec; `None` keeps the daemon's own.
+    ///
+    /// Switching identity requires root – an unprivileged daemon runs every
+    /// service as itself and merely warns about explicit `user`/`group`.
+    fn credentials(&self) -> Result<Option<Credentials>, CredentialError> {
+        if !Uid::effective().is_root() {
+            if self.def.user.is_some() || self.def.group.is_some() {
+                warn!(
+                    "{}: daemon is not root – ignoring user/group and running as uid {}",
+                    self.name,
+                    Uid::effective()
+                );
+            }
+            return Ok(None);
+        }
+        let creds = Credentials::resolve(
+            self.def.user.as_deref(),
+            self.def.group.as_deref(),
+            self.defaults.default_user.as_deref(),
+            self.defaults.default_group.as_deref(),
+        )?;
+        Ok(creds.filter(|c| !c.is_current()))
+    }
+
+    fn kill_process(&mut self) -> Result<()> {
+        self.stop_child()?;
+        self.handle(Event::StopDone)
+    }
+
+    /// Stop the current run, if any, and clean up after it.
+    fn stop_child(&mut self) -> Result<()> {
+        if self.child.is_some() {
+            self.run_hooks(Stage::PreStop).ok();
+        }
+        if let Some(mut ch) = self.child.take() {
             let pid = ch.id();
-            ch.kill().ok();
-            self.bus.send(Evt::State {
+            let exit = process::terminate_group(&mut ch, self.stop_signal(), self.stop_timeout())
+                .map(ExitInfo::from);
+            self.bus.send(Evt::Exit {
                 service: self.name.to_string(),
-                kind: "stopped",
+                pid,
+                exit,
+                runtime: self.started.elapsed(),
+                expected: true,
                 ts: Utc::now(),
-                pid: Some(pid),
             })?;
-            info!("{} stopped", self.name);
+            match exit {
+                Some(exit) => info!("{} stopped ({exit})", self.name),
+                None => info!("{} stopped", self.name),
+            }
+        }
+        self.end_run()
+    }
+
+    fn notify_unhealthy(&mut self) -> Result<()> {
+        let (failures, reason) = self
+            .failure
+            .take()
+            .unwrap_or_else(|| (1, "unknown reason".to_string()));
+        self.bus.send(Evt::Health {
+            service: self.name.to_string(),
+            healthy: false,
+            failures,
+            reason: Some(reason),
+            ts: Utc::now(),
+        })?;
+        Ok(())
+    }
+
+    fn stop_signal(&self) -> Signal {
+        let Some(raw) = self.def.stop_signal.as_deref() else {
+            return Signal::SIGTERM;
+        };
+        process::parse_signal(raw).unwrap_or_else(|| {
+            warn!("{}: unknown stop_signal '{raw}', using SIGTERM", self.name);
+            Signal::SIGTERM
+        })
+    }
+
+    fn stop_timeout(&self) -> Duration {
+        self.def
+            .stop_timeout_s
+            .map_or(process::DEFAULT_STOP_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Collect the child if it exited on its own and feed `ProcExit`.
+    /// Returns whether it had.
+    fn reap(&mut self) -> Result<bool> {
+        let Some(ch) = self.child.as_mut() else {
+            return Ok(false);
+        };
+        let Some(status) = ch.try_wait().ok().flatten() else {
+            return Ok(false);
+        };
+        let pid = ch.id();
+        self.child = None;
+        let exit = ExitInfo::from(status);
+        let runtime = self.started.elapsed();
+        self.bus.send(Evt::Exit {
+            service: self.name.to_string(),
+            pid,
+            exit: Some(exit),
+            runtime,
+            expected: false,
+            ts: Utc::now(),
+        })?;
+        // A socket‑activated service exiting cleanly waits for the next
+        // connection.
+        let completed =
+            exit.success() && (self.def.runs_to_completion() || self.def.socket_activated());
+        if completed {
+            info!("{} (pid {pid}) completed after {runtime:?}", self.name);
+        } else {
+            warn!(
+                "{} (pid {pid}) exited with {exit} after {runtime:?}",
+                self.name
+            );
+        }
+        self.end_run()?;
+        if let Some(probe) = self.health.as_mut() {
+            probe.reset();
+        }
+        if completed {
+            self.handle(Event::ProcDone)?;
+            self.wait_for_connection();
+            return Ok(true);
+        }
+        self.failure = Some((1, format!("process exited with {exit}")));
+        self.handle(Event::ProcExit)?;
+        Ok(true)
+    }
+
+    fn ready_timeout(&self) -> Duration {
+        self.def
+            .readiness
+            .as_ref()
+            .map_or(Duration::ZERO, |r| Duration::from_secs(r.timeout_s))
+    }
+
+    /// The current run reported ready.
+    fn became_ready(&mut self) -> Result<()> {
+        self.ready_by = None;
+        self.ready = never();
+        info!("{} ready after {:?}", self.name, self.started.elapsed());
+        self.started()
+    }
+
+    /// The current run is up: run the `post_start` hooks, then count it as
+    /// running – or stop it again if one of them aborts the start.
+    fn started(&mut self) -> Result<()> {
+        if let Err(e) = self.run_hooks(Stage::PostStart) {
+            error!("{}: {e}", self.name);
+            self.stop_child()?;
+            self.failure = Some((1, e.to_string()));
+            return self.handle(Event::StartErr);
+        }
+        self.handle(Event::StartedOk)
+    }
+
+    fn run_hooks(&self, stage: Stage) -> Result<(), hooks::HookError> {
+        let Some(env) = &self.hook_env else {
+            return Ok(());
+        };
+        hooks::run(self.name, stage, self.def.hooks.as_ref(), env, &self.logs)
+    }
+
+    /// One `tcp-port` readiness attempt.
+    fn poll_ready(&mut self) -> Result<()> {
+        if let Readiness::TcpPort { host, port } = &self.readiness
+            && readiness::tcp_ready(host, *port)
+        {
+            return self.became_ready();
         }
         Ok(())
     }
 
-    fn health_check(&self, child: &mut Option<Child>) -> Result<()> {
-        let healthy = child
+    /// The current run missed its readiness deadline: stop it and fail the
+    /// start.
+    fn not_ready(&mut self) -> Result<()> {
+        let timeout = self.ready_timeout();
+        error!("{}: not ready within {timeout:?} – stopping it", self.name);
+        self.stop_child()?;
+        self.failure = Some((1, format!("not ready within {timeout:?}")));
+        self.handle(Event::StartErr)
+    }
+
+    /// Gracefully restart after `watch_dirs` changes settled.
+    fn reload(&mut self) -> Result<()> {
+        self.reload_at = None;
+        if !self.lifecycle.is_running() {
+            return Ok(());
+        }
+        info!("{}: watch_dirs changed – restarting", self.name);
+        self.handle(Event::CmdRestart)
+    }
+
+    fn health_check(&mut self) -> Result<()> {
+        // Nothing to probe while the service is deliberately stopped or
+        // still coming up.
+        if self.child.is_none() || self.reap()? || self.lifecycle.state() == State::Starting {
+            return Ok(());
+        }
+        if let Some(memfs) = &self.memfs
+            && let Some(used) = memfs.over_limit()
+        {
+            self.failure = Some((
+                1,
+                format!(
+                    "memfs {} uses {used} MB of its {} MB",
+                    memfs.path().display(),
+                    memfs.limit_mb()
+                ),
+            ));
+            return self.handle(Event::HealthBad);
+        }
+        let verdict = self
+            .health
             .as_mut()
-            .is_some_and(|c| c.try_wait().ok().flatten().is_none());
-        self.bus.send(Evt::Health {
+            .map_or(Verdict::Healthy, HealthProbe::check);
+        let event = match verdict {
+            Verdict::Healthy => {
+                // Without a probe there is only news when a memfs overrun
+                // has cleared up.
+                if self.health.is_some() || self.lifecycle.state() == State::Failed {
+                    self.bus.send(Evt::Health {
+                        service: self.name.to_string(),
+                        healthy: true,
+                        failures: 0,
+                        reason: None,
+                        ts: Utc::now(),
+                    })?;
+                }
+                Event::HealthOk
+            }
+            Verdict::Degraded { failures, reason } => {
+                warn!(
+                    "{} health probe failed ({failures} in a row): {reason}",
+                    self.name
+                );
+                return Ok(());
+            }
+            Verdict::Unhealthy { failures, reason } => {
+                self.failure = Some((failures, reason));
+                Event::HealthBad
+            }
+        };
+        // Recovery (restart policy, on_failure actions) is the manager's call.
+        self.handle(event)
+    }
+
+    fn report_usage(&mut self) -> Result<()> {
+        let Some(cgroup) = &self.cgroup else {
+            return Ok(());
+        };
+        let usage = cgroup.usage();
+        self.bus.send(Evt::Resources {
             service: self.name.to_string(),
-            healthy,
+            memory_bytes: usage.memory_bytes,
+            cpu_usec: usage.cpu_usec,
             ts: Utc::now(),
         })?;
-        if !healthy && self.def.auto_restart {
-            warn!("{} unhealthy → restart", self.name);
-            self.tx.send(Cmd::Restart).ok(); // self‑loop via channel (constant‑time, no alloc)
+        if usage.oom_kills > self.oom_kills {
+            self.oom_kills = usage.oom_kills;
+            self.bus.send(Evt::OomKill {
+                service: self.name.to_string(),
+                kills: usage.oom_kills,
+                ts: Utc::now(),
+            })?;
         }
         Ok(())
     }
 
     fn rotate_logs(&self) -> Result<()> {
-        // (implementation stripped for brevity; same algorithm as original)
+        let Some(cfg) = &self.def.log_rotation else {
+            return Ok(());
+        };
+        if self.logs.rotate(cfg) == 0 {
+            return Ok(());
+        }
         self.bus.send(Evt::LogRotate {
             service: self.name.to_string(),
             ts: Utc::now(),
@@ -157,12 +1006,16 @@ impl ServiceWorker {
 }
 
 /// Public function to spawn a service worker
-pub fn spawn(def: ServiceDefinition, bus: Sender<Evt>) -> Result<Sender<Cmd>, ServiceError> {
+pub fn spawn(
+    def: ServiceDefinition,
+    bus: Sender<Evt>,
+    defaults: &WorkerDefaults,
+) -> Result<Sender<Cmd>, ServiceError> {
     // Check if this is the special autoconfig service
     if def.name == "kodegen-autoconfig" || def.service_type == Some("autoconfig".to_string()) {
         return autoconfig::spawn_autoconfig(def, bus);
     }
 
     // Otherwise spawn normal service
-    ServiceWorker::spawn(def, bus)
+    ServiceWorker::spawn(def, bus, defaults)
 }
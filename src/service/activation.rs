@@ -0,0 +1,420 @@
+//! `socket_activation`: kodegend owns a service's listening sockets and
+//! starts the service on the first connection.
+//!
+//! The sockets are handed over with the `LISTEN_FDS` protocol of
+//! `sd_listen_fds(3)`: descriptors 3, 4, … in the order of `listen`, with
+//! `LISTEN_FDS`, `LISTEN_PID` and `LISTEN_FDNAMES` set.  While the service
+//! runs it accepts on them itself; once it exits cleanly – or has been idle
+//! for `idle_timeout_s` – kodegend waits for the next connection again.
+//!
+//! Idle detection reads the kernel's connection tables in `/proc/net`, so
+//! `idle_timeout_s` is refused on other platforms.
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError, bounded};
+use thiserror::Error;
+
+use crate::config::SocketActivationConfig;
+
+/// Environment variables of the `LISTEN_FDS` protocol.
+pub const FDS_ENV: &str = "LISTEN_FDS";
+pub const PID_ENV: &str = "LISTEN_PID";
+pub const NAMES_ENV: &str = "LISTEN_FDNAMES";
+
+/// First descriptor passed to the service.
+const FIRST_FD: RawFd = 3;
+
+/// Permissions of Unix socket files unless `socket_mode` says otherwise.
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+
+/// How often the watcher notices that the listeners were closed.
+const WATCH_POLL_MS: libc::c_int = 500;
+
+/// How often a running service is checked for open connections.
+pub const IDLE_CHECK: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum ActivationError {
+    #[error("no sockets to listen on")]
+    Empty,
+
+    #[error("invalid listen address '{0}' (expected host:port or an absolute path)")]
+    Address(String),
+
+    #[error("cannot listen on '{listen}': {source}")]
+    Bind {
+        listen: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("cannot watch the sockets: {0}")]
+    Watch(#[source] io::Error),
+
+    #[error("idle_timeout_s needs Linux, where open connections can be seen")]
+    IdleUnsupported,
+}
+
+/// Refuse what this platform cannot do.
+pub fn check_supported(cfg: &SocketActivationConfig) -> Result<(), ActivationError> {
+    if cfg.idle_timeout_s.is_some() && !cfg!(target_os = "linux") {
+        return Err(ActivationError::IdleUnsupported);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(TcpListener),
+    Unix { socket: UnixListener, path: PathBuf },
+}
+
+impl Listener {
+    fn bind(listen: &str, mode: u32) -> Result<Self, ActivationError> {
+        let bind_err = |source| ActivationError::Bind {
+            listen: listen.to_string(),
+            source,
+        };
+        if listen.starts_with('/') {
+            let path = PathBuf::from(listen);
+            // Replace a socket left behind by an earlier run, nothing else.
+            if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                fs::remove_file(&path).map_err(bind_err)?;
+            }
+            let socket = UnixListener::bind(&path).map_err(bind_err)?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).map_err(bind_err)?;
+            return Ok(Self::Unix { socket, path });
+        }
+        let addr = listen
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| ActivationError::Address(listen.to_string()))?;
+        TcpListener::bind(addr).map(Self::Tcp).map_err(bind_err)
+    }
+
+    fn fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(socket) => socket.as_raw_fd(),
+            Self::Unix { socket, .. } => socket.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix { path, .. } = self {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// The bound sockets of one service and the thread watching them.
+#[derive(Debug)]
+pub struct Listeners {
+    listeners: Vec<Listener>,
+    /// Pings once a connection is waiting; the watcher then pauses until
+    /// `arm` is called.
+    incoming: Receiver<()>,
+    arm: Sender<()>,
+}
+
+impl Listeners {
+    pub fn bind(service: &str, cfg: &SocketActivationConfig) -> Result<Self, ActivationError> {
+        if cfg.listen.is_empty() {
+            return Err(ActivationError::Empty);
+        }
+        let mode = cfg.socket_mode.unwrap_or(DEFAULT_SOCKET_MODE);
+        let listeners = cfg
+            .listen
+            .iter()
+            .map(|listen| Listener::bind(listen, mode))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fds: Vec<RawFd> = listeners.iter().map(Listener::fd).collect();
+        let (ping, incoming) = bounded(1);
+        let (arm, armed) = bounded(1);
+        // The thread only polls the descriptors; it stops once `arm` is
+        // dropped together with the listeners.
+        thread::Builder::new()
+            .name(format!("listen-{service}"))
+            .spawn(move || watch(&fds, &armed, &ping))
+            .map_err(ActivationError::Watch)?;
+        Ok(Self {
+            listeners,
+            incoming,
+            arm,
+        })
+    }
+
+    /// Pings when a connection arrives after `arm`.
+    #[must_use]
+    pub fn incoming(&self) -> &Receiver<()> {
+        &self.incoming
+    }
+
+    /// Watch for the next connection.
+    pub fn arm(&self) {
+        self.arm.try_send(()).ok();
+    }
+
+    /// Pass the sockets to `cmd` as descriptors 3, 4, … and set the
+    /// `LISTEN_*` variables other than `LISTEN_PID`, which only the child
+    /// knows (see `exec_line`).
+    pub fn pass(&self, service: &str, cmd: &mut Command) {
+        let fds: Vec<RawFd> = self.listeners.iter().map(Listener::fd).collect();
+        cmd.env(FDS_ENV, fds.len().to_string())
+            .env(NAMES_ENV, vec![service; fds.len()].join(":"));
+        // SAFETY: `hand_over` only issues `fcntl(2)` and `dup2(2)`.
+        unsafe {
+            cmd.pre_exec(move || hand_over(&fds));
+        }
+    }
+
+    /// Whether the service has a connection open or waiting.
+    #[must_use]
+    pub fn busy(&self) -> bool {
+        let fds: Vec<RawFd> = self.listeners.iter().map(Listener::fd).collect();
+        if readable(&fds, 0).unwrap_or(false) {
+            return true;
+        }
+        self.listeners.iter().any(|listener| match listener {
+            Listener::Tcp(socket) => socket.local_addr().is_ok_and(tcp_connected),
+            Listener::Unix { path, .. } => unix_connected(path),
+        })
+    }
+}
+
+/// The shell line running `command` for a socket‑activated service.  The
+/// shell exports its own pid as `LISTEN_PID` and execs the command in its
+/// place, so the command must be a single one.
+#[must_use]
+pub fn exec_line(command: &str) -> String {
+    format!("{PID_ENV}=$$; export {PID_ENV}; exec {command}")
+}
+
+fn watch(fds: &[RawFd], armed: &Receiver<()>, ping: &Sender<()>) {
+    while armed.recv().is_ok() {
+        loop {
+            match readable(fds, WATCH_POLL_MS) {
+                Ok(true) => {
+                    ping.try_send(()).ok();
+                    break;
+                }
+                Ok(false) => {}
+                Err(_) => return,
+            }
+            if matches!(armed.try_recv(), Err(TryRecvError::Disconnected)) {
+                return;
+            }
+        }
+    }
+}
+
+/// Whether any of `fds` has a connection waiting within `timeout_ms`.
+fn readable(fds: &[RawFd], timeout_ms: libc::c_int) -> io::Result<bool> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: `pollfds` is a valid array of `pollfd.len()` entries.
+    let rc = unsafe {
+        libc::poll(
+            pollfds.as_mut_ptr(),
+            pollfds.len() as libc::nfds_t,
+            timeout_ms,
+        )
+    };
+    match rc {
+        0 => Ok(false),
+        rc if rc > 0 => {
+            if pollfds.iter().any(|p| p.revents & libc::POLLNVAL != 0) {
+                return Err(io::Error::from_raw_os_error(libc::EBADF));
+            }
+            Ok(true)
+        }
+        _ => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Move `fds` to 3, 4, … without close‑on‑exec.  Runs between fork and
+/// exec.
+///
+/// Everything is first copied above the target range so no source is
+/// overwritten before it has been moved.  Whatever else sat in the target
+/// range is replaced – including, possibly, `Command`'s exec error pipe, in
+/// which case an exec failure shows up as an exit instead.
+fn hand_over(fds: &[RawFd]) -> io::Result<()> {
+    let above = FIRST_FD + fds.len() as RawFd;
+    let mut moved = Vec::with_capacity(fds.len());
+    for &fd in fds {
+        // SAFETY: plain descriptor syscalls.
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, above) };
+        if copy < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        moved.push(copy);
+    }
+    for (target, copy) in (FIRST_FD..).zip(moved) {
+        // SAFETY: plain descriptor syscalls.
+        if unsafe { libc::dup2(copy, target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether an established TCP connection was accepted on `listener`: its
+/// local address is the listener's, or any of the same port if the
+/// listener is bound to the unspecified address.
+fn tcp_connected(listener: SocketAddr) -> bool {
+    const ESTABLISHED: &str = "01";
+    let accepted_on = |local: SocketAddr| {
+        local.port() == listener.port()
+            && (listener.ip().is_unspecified()
+                || local.ip().to_canonical() == listener.ip().to_canonical())
+    };
+    ["/proc/net/tcp", "/proc/net/tcp6"].iter().any(|table| {
+        fs::read_to_string(table).is_ok_and(|table| {
+            table.lines().skip(1).any(|line| {
+                // sl local_address rem_address st …
+                let mut fields = line.split_whitespace().skip(1);
+                let local = fields.next().and_then(parse_proc_addr);
+                local.is_some_and(accepted_on) && fields.nth(1) == Some(ESTABLISHED)
+            })
+        })
+    })
+}
+
+/// Parse an address of `/proc/net/tcp{,6}`: the address as 32-bit words
+/// in host byte order, then the port, all in hex (`0100007F:1F90`).
+fn parse_proc_addr(field: &str) -> Option<SocketAddr> {
+    let (addr, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for word in addr.as_bytes().chunks(8) {
+        let word = std::str::from_utf8(word).ok()?;
+        bytes.extend(u32::from_str_radix(word, 16).ok()?.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Whether a connection accepted on the Unix socket at `path` is open;
+/// those carry the listener's path.
+fn unix_connected(path: &std::path::Path) -> bool {
+    const CONNECTED: &str = "03";
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    fs::read_to_string("/proc/net/unix").is_ok_and(|table| {
+        table.lines().skip(1).any(|line| {
+            // Num RefCount Protocol Flags Type St Inode Path
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.get(5) == Some(&CONNECTED) && fields.get(7) == Some(&path)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Stdio;
+
+    use super::*;
+
+    #[test]
+    fn exec_line_exports_the_service_pid() {
+        let line = exec_line("sh -c 'echo $LISTEN_PID $$'");
+        assert_eq!(
+            line,
+            "LISTEN_PID=$$; export LISTEN_PID; exec sh -c 'echo $LISTEN_PID $$'"
+        );
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg(&line)
+            .output()
+            .expect("run sh");
+        let out = String::from_utf8_lossy(&out.stdout);
+        let pids: Vec<&str> = out.split_whitespace().collect();
+        assert_eq!(pids.len(), 2, "{out:?}");
+        assert_eq!(pids[0], pids[1]);
+    }
+
+    #[test]
+    fn parses_proc_net_addresses() {
+        if cfg!(target_endian = "little") {
+            assert_eq!(
+                parse_proc_addr("0100007F:1F90"),
+                Some("127.0.0.1:8080".parse().expect("address"))
+            );
+            assert_eq!(
+                parse_proc_addr("00000000000000000000000001000000:0050"),
+                Some("[::1]:80".parse().expect("address"))
+            );
+        }
+        assert_eq!(parse_proc_addr("garbage"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hands_sockets_over_as_3_and_4() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let unix = dir.path().join("svc.sock");
+        let cfg = SocketActivationConfig {
+            listen: vec![
+                "127.0.0.1:0".to_string(),
+                unix.to_string_lossy().into_owned(),
+            ],
+            idle_timeout_s: None,
+            socket_mode: None,
+        };
+        let listeners = Listeners::bind("svc", &cfg).expect("bind");
+        let expected: Vec<String> = listeners
+            .listeners
+            .iter()
+            .map(|l| {
+                fs::read_link(format!("/proc/self/fd/{}", l.fd()))
+                    .expect("listener fd")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("echo $LISTEN_FDS $LISTEN_FDNAMES; readlink /proc/$$/fd/3 /proc/$$/fd/4")
+            .stdout(Stdio::piped());
+        listeners.pass("svc", &mut cmd);
+        let out = cmd.output().expect("run sh");
+        let out = String::from_utf8_lossy(&out.stdout);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.first(), Some(&"2 svc:svc"), "{out:?}");
+        assert_eq!(lines[1..], [expected[0].as_str(), expected[1].as_str()]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Per‑service cgroup v2 groups for `ServiceDefinition::resources`.
+//!
+//! The daemon's own cgroup (delegated by systemd with `Delegate=`) becomes an
+//! inner node on first use: everything in it moves to a `daemon` leaf –
+//! cgroup v2 allows no processes in a group that hands controllers down – and
+//! the memory, cpu, pids and io controllers are enabled for its children.
+//! Each service run then gets its own `svc-<name>` group, which the child
+//! joins between fork and exec so nothing it forks can escape the limits.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use thiserror::Error;
+
+use crate::config::ResourcesConfig;
+
+/// `cpu.max` period; the quota is derived from `cpu_quota_percent`.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Controllers handed down to service groups, when available.
+const CONTROLLERS: [&str; 4] = ["memory", "cpu", "pids", "io"];
+
+#[derive(Error, Debug)]
+pub enum CgroupError {
+    #[error("no cgroup v2 hierarchy is mounted")]
+    NoHierarchy,
+
+    #[error("cannot delegate controllers: {0}")]
+    Delegation(String),
+
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Accounting snapshot of one service group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CgroupUsage {
+    pub memory_bytes: u64,
+    pub cpu_usec: u64,
+    /// OOM kills inside the group since it was created.
+    pub oom_kills: u64,
+}
+
+/// The cgroup of one service run; removed on drop.
+#[derive(Debug)]
+pub struct ServiceCgroup {
+    path: PathBuf,
+    /// `cgroup.procs`, opened up front so the child only has to `write(2)`.
+    procs: File,
+}
+
+impl ServiceCgroup {
+    /// Create the group for `service` and apply `cfg`.
+    pub fn create(service: &str, cfg: &ResourcesConfig) -> Result<Self, CgroupError> {
+        let name: String = service
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.@".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = delegated_root()?.join(format!("svc-{name}"));
+
+        // An earlier run (or a crashed daemon) may have left it behind.
+        remove(&path);
+        fs::create_dir(&path).map_err(|source| CgroupError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let procs_path = path.join("cgroup.procs");
+        let procs = match OpenOptions::new().write(true).open(&procs_path) {
+            Ok(procs) => procs,
+            Err(source) => {
+                remove(&path);
+                return Err(CgroupError::Io {
+                    path: procs_path,
+                    source,
+                });
+            }
+        };
+        // From here on, drop removes the group again on error.
+        let group = Self { path, procs };
+        let path = &group.path;
+
+        if let Some(mb) = cfg.memory_max_mb {
+            write(path, "memory.max", &(mb * 1024 * 1024).to_string())?;
+        }
+        if let Some(weight) = cfg.cpu_weight {
+            write(path, "cpu.weight", &weight.clamp(1, 10_000).to_string())?;
+        }
+        if let Some(percent) = cfg.cpu_quota_percent {
+            let quota = CPU_PERIOD_USEC * u64::from(percent.max(1)) / 100;
+            write(path, "cpu.max", &format!("{quota} {CPU_PERIOD_USEC}"))?;
+        }
+        if let Some(max) = cfg.pids_max {
+            write(path, "pids.max", &max.to_string())?;
+        }
+        if let Some(weight) = cfg.io_weight {
+            write(
+                path,
+                "io.weight",
+                &format!("default {}", weight.clamp(1, 10_000)),
+            )?;
+        }
+        Ok(group)
+    }
+
+    /// Make the spawned child join this group before it execs.
+    pub fn apply(&self, cmd: &mut Command) {
+        let fd = self.procs.as_raw_fd();
+        // SAFETY: only `write(2)` on an fd opened before the fork.
+        unsafe {
+            cmd.pre_exec(move || {
+                // "0" means the writing process itself.
+                if libc::write(fd, b"0".as_ptr().cast(), 1) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Current memory and CPU usage plus the OOM kill count.
+    #[must_use]
+    pub fn usage(&self) -> CgroupUsage {
+        CgroupUsage {
+            memory_bytes: read_u64(&self.path.join("memory.current")).unwrap_or(0),
+            cpu_usec: keyed(&self.path.join("cpu.stat"), "usage_usec").unwrap_or(0),
+            oom_kills: keyed(&self.path.join("memory.events"), "oom_kill").unwrap_or(0),
+        }
+    }
+}
+
+impl Drop for ServiceCgroup {
+    fn drop(&mut self) {
+        remove(&self.path);
+    }
+}
+
+/// The daemon's own group, prepared to host service groups.  Set up once;
+/// a failure is remembered so later services do not retry it.
+fn delegated_root() -> Result<&'static Path, CgroupError> {
+    static ROOT: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+    ROOT.get_or_init(|| init_root().map_err(|e| e.to_string()))
+        .as_deref()
+        .map_err(|e| CgroupError::Delegation(e.to_string()))
+}
+
+fn init_root() -> Result<PathBuf, CgroupError> {
+    let mountinfo = read("/proc/self/mountinfo".as_ref())?;
+    // "<id> <parent> <maj:min> <root> <mount point> ... - <fstype> ..."
+    let mount = mountinfo
+        .lines()
+        .find(|line| {
+            line.split_once(" - ")
+                .is_some_and(|(_, rest)| rest.starts_with("cgroup2 "))
+        })
+        .and_then(|line| line.split(' ').nth(4))
+        .ok_or(CgroupError::NoHierarchy)?;
+    let own = read("/proc/self/cgroup".as_ref())?;
+    let own = own
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or(CgroupError::NoHierarchy)?;
+    let root = Path::new(mount).join(own.trim_start_matches('/'));
+
+    let leaf = root.join("daemon");
+    if let Err(source) = fs::create_dir(&leaf)
+        && source.kind() != io::ErrorKind::AlreadyExists
+    {
+        return Err(CgroupError::Io { path: leaf, source });
+    }
+    for pid in read(&root.join("cgroup.procs"))?.lines() {
+        // Processes may exit while we move them.
+        write(&leaf, "cgroup.procs", pid).ok();
+    }
+
+    let available = read(&root.join("cgroup.controllers"))?;
+    let enable: Vec<String> = CONTROLLERS
+        .iter()
+        .filter(|c| available.split_whitespace().any(|a| a == **c))
+        .map(|c| format!("+{c}"))
+        .collect();
+    if !enable.is_empty() {
+        write(&root, "cgroup.subtree_control", &enable.join(" "))?;
+    }
+    Ok(root)
+}
+
+fn read(path: &Path) -> Result<String, CgroupError> {
+    fs::read_to_string(path).map_err(|source| CgroupError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn write(dir: &Path, file: &str, value: &str) -> Result<(), CgroupError> {
+    let path = dir.join(file);
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(value.as_bytes()))
+        .map_err(|source| CgroupError::Io { path, source })
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Value of `key` in a flat‑keyed file such as `cpu.stat`.
+fn keyed(path: &Path, key: &str) -> Option<u64> {
+    fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.parse().ok())
+}
+
+/// Kill whatever is left in the group and remove it.
+fn remove(path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    // cgroup.kill needs Linux 5.14; without it the group is usually empty
+    // by now anyway.
+    write(path, "cgroup.kill", "1").ok();
+    let mut attempts = 0;
+    loop {
+        match fs::remove_dir(path) {
+            // Killed processes take a moment to leave.
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) && attempts < 20 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => {
+                warn!("cannot remove cgroup {}: {e}", path.display());
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+}
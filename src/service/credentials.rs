@@ -0,0 +1,152 @@
+//! Identity a service runs under.
+//!
+//! Names from `ServiceDefinition::user`/`group` (or the `ServiceConfig`
+//! defaults) are resolved in the worker *before* forking so lookup errors can
+//! be reported properly.  The child then only performs the raw
+//! `setgroups`/`setgid`/`setuid` syscalls between `fork` and `exec`.
+
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use nix::unistd::{Gid, Group, Uid, User};
+use thiserror::Error;
+
+/// Name resolution failures.
+#[derive(Error, Debug)]
+pub enum CredentialError {
+    #[error("user '{0}' does not exist")]
+    UnknownUser(String),
+
+    #[error("group '{0}' does not exist")]
+    UnknownGroup(String),
+
+    #[error("failed to look up '{name}': {source}")]
+    Lookup {
+        name: String,
+        #[source]
+        source: nix::Error,
+    },
+}
+
+impl CredentialError {
+    /// Short, static description suitable for `Evt::Fatal`.
+    #[must_use]
+    pub const fn fatal_msg(&self) -> &'static str {
+        match self {
+            Self::UnknownUser(_) => "configured user does not exist",
+            Self::UnknownGroup(_) => "configured group does not exist",
+            Self::Lookup { .. } => "user/group lookup failed",
+        }
+    }
+}
+
+/// Fully resolved identity for a child process.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// Target user; `None` keeps the daemon's uid and only switches group.
+    pub user: Option<User>,
+    pub gid: Gid,
+    /// Supplementary groups, including `gid`.
+    pub groups: Vec<Gid>,
+}
+
+impl Credentials {
+    /// Resolve the identity for a service.
+    ///
+    /// The group is, in order of preference: the explicit `group`, the
+    /// primary group of an explicitly configured `user`, `default_group`,
+    /// and finally the primary group of the (default) user.
+    pub fn resolve(
+        user: Option<&str>,
+        group: Option<&str>,
+        default_user: Option<&str>,
+        default_group: Option<&str>,
+    ) -> Result<Option<Self>, CredentialError> {
+        let user_name = user.or(default_user);
+        let group_name = group.or(if user.is_some() { None } else { default_group });
+        if user_name.is_none() && group_name.is_none() {
+            return Ok(None);
+        }
+
+        let user = user_name.map(lookup_user).transpose()?;
+        let gid = match (group_name, &user) {
+            (Some(name), _) => lookup_group(name)?.gid,
+            (None, Some(u)) => u.gid,
+            (None, None) => Gid::effective(),
+        };
+        let groups = match &user {
+            Some(u) => supplementary_groups(u, gid)?,
+            None => vec![gid],
+        };
+
+        Ok(Some(Self { user, gid, groups }))
+    }
+
+    /// Export the identity's environment and drop privileges in the child
+    /// right before `exec`.
+    pub fn apply(&self, cmd: &mut Command) {
+        if let Some(user) = &self.user {
+            cmd.env("USER", &user.name)
+                .env("LOGNAME", &user.name)
+                .env("HOME", &user.dir);
+        }
+
+        let uid = self.user.as_ref().map(|u| u.uid.as_raw());
+        let gid = self.gid.as_raw();
+        let groups: Vec<libc::gid_t> = self.groups.iter().map(|g| g.as_raw()).collect();
+
+        // SAFETY: the closure only issues async‑signal‑safe syscalls on data
+        // captured before the fork.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0
+                    || libc::setgid(gid) != 0
+                    || uid.is_some_and(|uid| libc::setuid(uid) != 0)
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Whether switching to this identity actually changes anything.
+    #[must_use]
+    pub fn is_current(&self) -> bool {
+        self.user.as_ref().is_none_or(|u| u.uid == Uid::effective()) && self.gid == Gid::effective()
+    }
+}
+
+fn lookup_user(name: &str) -> Result<User, CredentialError> {
+    User::from_name(name)
+        .map_err(|source| CredentialError::Lookup {
+            name: name.to_string(),
+            source,
+        })?
+        .ok_or_else(|| CredentialError::UnknownUser(name.to_string()))
+}
+
+fn lookup_group(name: &str) -> Result<Group, CredentialError> {
+    Group::from_name(name)
+        .map_err(|source| CredentialError::Lookup {
+            name: name.to_string(),
+            source,
+        })?
+        .ok_or_else(|| CredentialError::UnknownGroup(name.to_string()))
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn supplementary_groups(user: &User, gid: Gid) -> Result<Vec<Gid>, CredentialError> {
+    let name = std::ffi::CString::new(user.name.as_str())
+        .map_err(|_| CredentialError::UnknownUser(user.name.clone()))?;
+    nix::unistd::getgrouplist(&name, gid).map_err(|source| CredentialError::Lookup {
+        name: user.name.clone(),
+        source,
+    })
+}
+
+#[cfg(target_vendor = "apple")]
+fn supplementary_groups(_user: &User, gid: Gid) -> Result<Vec<Gid>, CredentialError> {
+    Ok(vec![gid])
+}
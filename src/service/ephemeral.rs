@@ -0,0 +1,95 @@
+//! `ephemeral_dir`: scratch space that lives exactly as long as one run.
+//!
+//! The directory is recreated empty before every start, handed to the
+//! child as `KODEGEN_EPHEMERAL_DIR`, and removed once the process is gone.
+
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use nix::unistd::{Gid, Uid, chown};
+
+/// Environment variable carrying the directory's path.
+pub const ENV_VAR: &str = "KODEGEN_EPHEMERAL_DIR";
+
+/// Resolve `raw` against the service's `working_dir` when relative.
+///
+/// The filesystem root is refused – it is about to be wiped.
+pub fn resolve(raw: &str, working_dir: Option<&str>) -> io::Result<PathBuf> {
+    let path = match working_dir {
+        Some(base) if Path::new(raw).is_relative() => Path::new(base).join(raw),
+        _ => PathBuf::from(raw),
+    };
+    if path.parent().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to use '{}' as ephemeral_dir", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+/// Replace whatever a previous run left at `path` with an empty `0700`
+/// directory owned by `owner`.
+pub fn prepare(path: &Path, owner: Option<(Option<Uid>, Gid)>) -> io::Result<()> {
+    wipe(path)?;
+    DirBuilder::new().recursive(true).mode(0o700).create(path)?;
+    if let Some((uid, gid)) = owner {
+        hand_over(path, uid, gid);
+    }
+    Ok(())
+}
+
+/// Give `path` to `uid`:`gid` unless it already belongs to them.  Without
+/// CAP_CHOWN this fails; the path then stays the daemon's and the service
+/// starts anyway, with a warning.
+pub fn hand_over(path: &Path, uid: Option<Uid>, gid: Gid) {
+    if let Ok(meta) = fs::symlink_metadata(path)
+        && uid.is_none_or(|uid| uid.as_raw() == meta.uid())
+        && gid.as_raw() == meta.gid()
+    {
+        return;
+    }
+    if let Err(e) = chown(path, uid, Some(gid)) {
+        warn!(
+            "cannot hand {} to the service's user and group: {e}",
+            path.display()
+        );
+    }
+}
+
+/// Remove `path` and everything below it; a missing directory is fine.
+pub fn wipe(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The daemon's own `kodegend-<euid>` directory below `parent`, a shared
+/// and possibly world-writable place such as `/dev/shm` or `/tmp`.
+///
+/// Anyone may have created that name first, so an existing entry is only
+/// used if it is a real directory – not a symlink – owned by the effective
+/// uid with mode 0700.
+pub fn private_base(parent: &Path) -> io::Result<PathBuf> {
+    let uid = Uid::effective();
+    let path = parent.join(format!("kodegend-{uid}"));
+    match DirBuilder::new().mode(0o700).create(&path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let meta = fs::symlink_metadata(&path)?;
+    if !meta.file_type().is_dir() || meta.uid() != uid.as_raw() || meta.mode() & 0o7777 != 0o700 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to use {}: not a 0700 directory owned by uid {uid}",
+                path.display()
+            ),
+        ));
+    }
+    Ok(path)
+}
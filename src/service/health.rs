@@ -0,0 +1,241 @@
+//! Active health probes driven by `HealthCheckConfig`.
+//!
+//! * `http`   – GET `target`; `expected_response` is either a status code
+//!   (`"204"`) or a substring the body must contain.  Without it any 2xx
+//!   status passes.
+//! * `tcp`    – connect to `target` (`host:port`).
+//! * `script` – run `target` through `sh -c`; exit 0 passes, and
+//!   `expected_response` (if set) must appear on stdout.
+//!
+//! A service only turns unhealthy after `retries` consecutive failures.
+
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::config::HealthCheckConfig;
+
+/// Probe interval used when a service has no `health_check` block.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often a running probe script is polled for completion.
+const SCRIPT_POLL: Duration = Duration::from_millis(50);
+
+/// Invalid `health_check` configuration.
+#[derive(Error, Debug)]
+pub enum HealthError {
+    #[error("unknown health check type '{0}' (expected http, tcp or script)")]
+    UnknownType(String),
+
+    #[error("failed to build HTTP client: {0}")]
+    HttpClient(#[from] reqwest::Error),
+}
+
+#[derive(Debug)]
+enum ProbeKind {
+    Http(reqwest::blocking::Client),
+    Tcp,
+    Script,
+}
+
+/// What the worker should report after a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Healthy,
+    /// Failed, but fewer than `retries` times in a row.
+    Degraded {
+        failures: u32,
+        reason: String,
+    },
+    Unhealthy {
+        failures: u32,
+        reason: String,
+    },
+}
+
+/// Configured probe plus its consecutive‑failure counter.
+#[derive(Debug)]
+pub struct HealthProbe {
+    cfg: HealthCheckConfig,
+    kind: ProbeKind,
+    failures: u32,
+}
+
+impl HealthProbe {
+    pub fn new(cfg: &HealthCheckConfig) -> Result<Self, HealthError> {
+        let kind = match cfg.check_type.to_ascii_lowercase().as_str() {
+            "http" => ProbeKind::Http(
+                reqwest::blocking::Client::builder()
+                    .timeout(Self::timeout_of(cfg))
+                    .build()?,
+            ),
+            "tcp" => ProbeKind::Tcp,
+            "script" => ProbeKind::Script,
+            other => return Err(HealthError::UnknownType(other.to_string())),
+        };
+        Ok(Self {
+            cfg: cfg.clone(),
+            kind,
+            failures: 0,
+        })
+    }
+
+    /// Time between two probes (`interval_secs`, at least one second).
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.cfg.interval_secs.max(1))
+    }
+
+    /// Run the probe once and fold the outcome into the failure counter.
+    pub fn check(&mut self) -> Verdict {
+        match self.probe() {
+            Ok(()) => {
+                self.failures = 0;
+                Verdict::Healthy
+            }
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    /// Forget earlier failures, e.g. because the process was replaced.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    fn fail(&mut self, reason: String) -> Verdict {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.cfg.retries.max(1) {
+            Verdict::Unhealthy {
+                failures: self.failures,
+                reason,
+            }
+        } else {
+            Verdict::Degraded {
+                failures: self.failures,
+                reason,
+            }
+        }
+    }
+
+    fn timeout_of(cfg: &HealthCheckConfig) -> Duration {
+        Duration::from_secs(cfg.timeout_secs.max(1))
+    }
+
+    fn probe(&self) -> Result<(), String> {
+        match &self.kind {
+            ProbeKind::Http(client) => self.probe_http(client),
+            ProbeKind::Tcp => self.probe_tcp(),
+            ProbeKind::Script => self.probe_script(),
+        }
+    }
+
+    fn probe_http(&self, client: &reqwest::blocking::Client) -> Result<(), String> {
+        let resp = client
+            .get(&self.cfg.target)
+            .send()
+            .map_err(|e| format!("GET {} failed: {e}", self.cfg.target))?;
+        let status = resp.status();
+
+        match self.cfg.expected_response.as_deref() {
+            Some(code) if code.parse::<u16>().is_ok_and(|c| (100..600).contains(&c)) => {
+                if status.as_str() == code {
+                    Ok(())
+                } else {
+                    Err(format!("expected status {code}, got {status}"))
+                }
+            }
+            expected => {
+                if !status.is_success() {
+                    return Err(format!("unexpected status {status}"));
+                }
+                let Some(needle) = expected else {
+                    return Ok(());
+                };
+                let body = resp
+                    .text()
+                    .map_err(|e| format!("reading body failed: {e}"))?;
+                if body.contains(needle) {
+                    Ok(())
+                } else {
+                    Err(format!("response body does not contain '{needle}'"))
+                }
+            }
+        }
+    }
+
+    fn probe_tcp(&self) -> Result<(), String> {
+        let timeout = Self::timeout_of(&self.cfg);
+        let addrs = self
+            .cfg
+            .target
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {e}", self.cfg.target))?;
+
+        let mut last_err = format!("{} resolved to no addresses", self.cfg.target);
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(_) => return Ok(()),
+                Err(e) => last_err = format!("connect {addr} failed: {e}"),
+            }
+        }
+        Err(last_err)
+    }
+
+    fn probe_script(&self) -> Result<(), String> {
+        let needle = self.cfg.expected_response.as_deref();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.cfg.target)
+            .stdin(Stdio::null())
+            .stdout(if needle.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("cannot run script: {e}"))?;
+
+        // Drained while the script runs, so a full pipe cannot stall it.
+        let output = child.stdout.take().map(|mut pipe| {
+            thread::spawn(move || {
+                let mut stdout = String::new();
+                pipe.read_to_string(&mut stdout).ok();
+                stdout
+            })
+        });
+
+        let deadline = Instant::now() + Self::timeout_of(&self.cfg);
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() >= deadline => {
+                    child.kill().ok();
+                    child.wait().ok();
+                    return Err(format!("script timed out after {}s", self.cfg.timeout_secs));
+                }
+                Ok(None) => thread::sleep(SCRIPT_POLL),
+                Err(e) => return Err(format!("waiting for script failed: {e}")),
+            }
+        };
+        if !status.success() {
+            return Err(format!("script exited with {status}"));
+        }
+
+        let Some(needle) = needle else {
+            return Ok(());
+        };
+        let stdout = output
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        if stdout.contains(needle) {
+            Ok(())
+        } else {
+            Err(format!("script output does not contain '{needle}'"))
+        }
+    }
+}
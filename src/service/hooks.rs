@@ -0,0 +1,161 @@
+//! `hooks`: commands run around a service's start and stop.
+//!
+//! * `pre_start` – before the process is spawned.
+//! * `post_start` – once it has started (and is ready, with `readiness`),
+//!   before it counts as running.
+//! * `pre_stop` – before the stop signal of a deliberate stop.
+//! * `post_stop` – once a run is over, however it ended.
+//!
+//! Hooks run one after another through `sh -c`, as the service's user, in
+//! its `working_dir`, with its environment; their output goes to the
+//! service's log.  A hook that fails or outlives `timeout_s` either aborts
+//! the start (`on_failure = "abort"`, the default) or is only logged
+//! (`"warn"`).  Stopping cannot be aborted, so failed stop hooks are always
+//! only logged.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use nix::sys::signal::Signal;
+use thiserror::Error;
+
+use crate::config::{HookConfig, HookFailure, HooksConfig};
+use crate::ipc::ExitInfo;
+
+use super::credentials::Credentials;
+use super::logs::LogCapture;
+use super::process;
+
+/// How often a running hook is checked for having exited.
+const HOOK_POLL: Duration = Duration::from_millis(50);
+
+/// Grace period between SIGTERM and SIGKILL for a hook past its timeout.
+const HOOK_KILL_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    PreStart,
+    PostStart,
+    PreStop,
+    PostStop,
+}
+
+impl Stage {
+    fn hooks(self, cfg: &HooksConfig) -> &[HookConfig] {
+        match self {
+            Self::PreStart => &cfg.pre_start,
+            Self::PostStart => &cfg.post_start,
+            Self::PreStop => &cfg.pre_stop,
+            Self::PostStop => &cfg.post_stop,
+        }
+    }
+
+    fn can_abort(self) -> bool {
+        matches!(self, Self::PreStart | Self::PostStart)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PreStart => "pre_start",
+            Self::PostStart => "post_start",
+            Self::PreStop => "pre_stop",
+            Self::PostStop => "post_stop",
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{stage} hook '{command}' {reason}")]
+pub struct HookError {
+    pub stage: Stage,
+    pub command: String,
+    pub reason: String,
+}
+
+/// What every hook of one run inherits from the service.
+#[derive(Debug, Clone, Default)]
+pub struct HookEnv {
+    pub vars: HashMap<String, String>,
+    pub working_dir: Option<String>,
+    pub creds: Option<Credentials>,
+}
+
+/// Run the `stage` hooks of `cfg` in order.  Stops at, and returns, the
+/// first failure that aborts the start.
+pub fn run(
+    service: &str,
+    stage: Stage,
+    cfg: Option<&HooksConfig>,
+    env: &HookEnv,
+    logs: &LogCapture,
+) -> Result<(), HookError> {
+    let Some(cfg) = cfg else {
+        return Ok(());
+    };
+    for hook in stage.hooks(cfg) {
+        let started = Instant::now();
+        match run_one(hook, env, logs) {
+            Ok(()) => info!(
+                "{service}: {stage} hook '{}' done after {:?}",
+                hook.command,
+                started.elapsed()
+            ),
+            Err(reason) => {
+                let err = HookError {
+                    stage,
+                    command: hook.command.clone(),
+                    reason,
+                };
+                if stage.can_abort() && hook.on_failure == HookFailure::Abort {
+                    return Err(err);
+                }
+                warn!("{service}: {err}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_one(hook: &HookConfig, env: &HookEnv, logs: &LogCapture) -> Result<(), String> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(&hook.command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .envs(&env.vars);
+    if let Some(dir) = &env.working_dir {
+        cmd.current_dir(dir);
+    }
+    if let Some(creds) = &env.creds {
+        creds.apply(&mut cmd);
+    }
+    let mut child = cmd.spawn().map_err(|e| format!("could not run: {e}"))?;
+    logs.attach(&mut child, None);
+
+    let timeout = Duration::from_secs(hook.timeout_s);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().ok().flatten() {
+            let exit = ExitInfo::from(status);
+            return if exit.success() {
+                Ok(())
+            } else {
+                Err(format!("failed with {exit}"))
+            };
+        }
+        if Instant::now() >= deadline {
+            process::terminate_group(&mut child, Signal::SIGTERM, HOOK_KILL_GRACE);
+            return Err(format!("timed out after {timeout:?}"));
+        }
+        thread::sleep(HOOK_POLL);
+    }
+}
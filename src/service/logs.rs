@@ -0,0 +1,530 @@
+//! Per-service output capture.
+//!
+//! Every supervised child gets its stdout and stderr piped back into the
+//! worker.  One reader thread per stream stamps each line, appends it to
+//! `<log_dir>/<service>.out.log` / `<service>.err.log`, and keeps the newest
+//! lines in a bounded in-memory ring so they can be pulled on demand.
+//!
+//! Rotation swaps the live file underneath the reader threads, so the child
+//! keeps writing into the same pipes and never needs a restart.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use crossbeam_channel::Sender;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::{error, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::LogRotationConfig;
+use crate::service::secrets;
+
+/// Number of lines retained in the in-memory tail buffer of each worker.
+pub const TAIL_CAPACITY: usize = 1000;
+
+/// Which child stream a line came from.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    /// File-name infix used for this stream (`<service>.<suffix>.log`).
+    #[must_use]
+    pub const fn suffix(self) -> &'static str {
+        match self {
+            Self::Stdout => "out",
+            Self::Stderr => "err",
+        }
+    }
+}
+
+/// One captured line of child output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLine {
+    /// Position in the service's output, counting from 1 across both
+    /// streams; `logs --follow` resumes after it.
+    pub seq: u64,
+    pub ts: DateTime<Utc>,
+    pub stream: LogStream,
+    pub text: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            self.ts.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.stream.suffix(),
+            self.text
+        )
+    }
+}
+
+/// Pings `notify` once a stdout line matches `pattern`.
+pub struct LineWatch {
+    pub pattern: Regex,
+    pub notify: Sender<()>,
+}
+
+/// Live log file of a single stream.
+struct LiveFile {
+    /// `None` when no `log_dir` is configured or the file could not be
+    /// opened; lines then only reach the tail buffer.
+    file: Option<File>,
+    /// When the current file was started – drives age-based rotation.
+    opened: DateTime<Utc>,
+}
+
+type SharedFile = Arc<Mutex<LiveFile>>;
+
+/// Output capture for one service: log files plus the tail ring.
+pub struct LogCapture {
+    service: &'static str,
+    dir: Option<PathBuf>,
+    out: SharedFile,
+    err: SharedFile,
+    tail: Arc<Mutex<VecDeque<LogLine>>>,
+}
+
+impl LogCapture {
+    /// Prepare capture for `service`, creating `log_dir` if necessary.
+    ///
+    /// Failing to create the directory or open a file is not fatal: the
+    /// service still runs and its output is still kept in the tail buffer.
+    pub fn new(service: &'static str, log_dir: Option<&Path>) -> Self {
+        let dir = log_dir.and_then(|dir| match fs::create_dir_all(dir) {
+            Ok(()) => Some(dir.to_path_buf()),
+            Err(e) => {
+                warn!(
+                    "{service}: cannot create log dir {}: {e} – output kept in memory only",
+                    dir.display()
+                );
+                None
+            }
+        });
+
+        let capture = Self {
+            service,
+            dir,
+            out: Arc::new(Mutex::new(LiveFile::closed())),
+            err: Arc::new(Mutex::new(LiveFile::closed())),
+            tail: Arc::new(Mutex::new(VecDeque::with_capacity(TAIL_CAPACITY))),
+        };
+        capture.reopen(LogStream::Stdout);
+        capture.reopen(LogStream::Stderr);
+        capture
+    }
+
+    /// Path of the live log file for `stream`, if file logging is enabled.
+    #[must_use]
+    pub fn path(&self, stream: LogStream) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.{}.log", self.service, stream.suffix())))
+    }
+
+    /// (Re)open the live file for `stream` in append mode, replacing the
+    /// handle the reader threads write through.
+    pub fn reopen(&self, stream: LogStream) {
+        let Some(path) = self.path(stream) else {
+            return;
+        };
+        let live = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => LiveFile::open(file),
+            Err(e) => {
+                warn!("{}: cannot open {}: {e}", self.service, path.display());
+                LiveFile::closed()
+            }
+        };
+        if let Ok(mut slot) = self.handle(stream).lock() {
+            *slot = live;
+        }
+    }
+
+    /// Rotate every stream whose live file exceeds `max_size_mb` or is older
+    /// than `interval_days`.  Returns how many files were rotated.
+    pub fn rotate(&self, cfg: &LogRotationConfig) -> usize {
+        let mut rotated = 0;
+        for stream in [LogStream::Stdout, LogStream::Stderr] {
+            let Some(path) = self.path(stream) else {
+                continue;
+            };
+            match self.rotate_stream(stream, &path, cfg) {
+                Ok(Some(archive)) => {
+                    info!(
+                        "{}: rotated {} → {}",
+                        self.service,
+                        path.display(),
+                        archive.display()
+                    );
+                    rotated += 1;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("{}: rotating {} failed: {e}", self.service, path.display()),
+            }
+        }
+        rotated
+    }
+
+    /// Take the child's stdout/stderr pipes and start one reader thread
+    /// per stream.  Threads exit on their own once the child closes the pipe.
+    /// `watch` is applied to stdout.
+    pub fn attach(&self, child: &mut Child, watch: Option<LineWatch>) {
+        if let Some(out) = child.stdout.take() {
+            self.pump(LogStream::Stdout, out, watch);
+        }
+        if let Some(err) = child.stderr.take() {
+            self.pump(LogStream::Stderr, err, None);
+        }
+    }
+
+    /// Newest `n` captured lines, oldest first.
+    #[must_use]
+    pub fn tail(&self, n: usize) -> Vec<LogLine> {
+        match self.tail.lock() {
+            Ok(ring) => ring
+                .iter()
+                .skip(ring.len().saturating_sub(n))
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn handle(&self, stream: LogStream) -> &SharedFile {
+        match stream {
+            LogStream::Stdout => &self.out,
+            LogStream::Stderr => &self.err,
+        }
+    }
+
+    /// Rotate one stream if it is due, returning the final archive path.
+    fn rotate_stream(
+        &self,
+        stream: LogStream,
+        path: &Path,
+        cfg: &LogRotationConfig,
+    ) -> io::Result<Option<PathBuf>> {
+        let archive = {
+            let Ok(mut slot) = self.handle(stream).lock() else {
+                return Ok(None);
+            };
+            let Some(file) = slot.file.as_ref() else {
+                return Ok(None);
+            };
+            let size = file.metadata()?.len();
+            let too_big = cfg.max_size_mb > 0 && size >= cfg.max_size_mb * 1024 * 1024;
+            let too_old = cfg.interval_days > 0
+                && Utc::now() - slot.opened >= TimeDelta::days(i64::from(cfg.interval_days));
+            if size == 0 || !(too_big || too_old) {
+                return Ok(None);
+            }
+
+            let archive = if cfg.timestamp {
+                timestamped_name(path)
+            } else {
+                shift_numbered(path, cfg.max_files)?;
+                append_ext(path, "1")
+            };
+            fs::rename(path, &archive)?;
+
+            // Swap in a fresh file while still holding the lock so no line
+            // lands in the archive after it was renamed.
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            *slot = LiveFile::open(file);
+            archive
+        };
+
+        let archive = if cfg.compress {
+            gzip(&archive)?
+        } else {
+            archive
+        };
+        prune(path, cfg.max_files)?;
+        Ok(Some(archive))
+    }
+
+    fn pump<R: Read + Send + 'static>(
+        &self,
+        stream: LogStream,
+        pipe: R,
+        mut watch: Option<LineWatch>,
+    ) {
+        let service = self.service;
+        let file = Arc::clone(self.handle(stream));
+        let tail = Arc::clone(&self.tail);
+
+        let spawned = thread::Builder::new()
+            .name(format!("log-{service}-{}", stream.suffix()))
+            .spawn(move || {
+                let mut reader = BufReader::new(pipe);
+                let mut buf = Vec::with_capacity(256);
+                loop {
+                    buf.clear();
+                    match reader.read_until(b'\n', &mut buf) {
+                        Ok(0) => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("{service}: reading {} failed: {e}", stream.suffix());
+                            break;
+                        }
+                    }
+                    while matches!(buf.last(), Some(b'\n' | b'\r')) {
+                        buf.pop();
+                    }
+                    let mut line = LogLine {
+                        seq: 0,
+                        ts: Utc::now(),
+                        stream,
+                        text: secrets::redact(&String::from_utf8_lossy(&buf)).into_owned(),
+                    };
+                    if let Some(w) = watch.take_if(|w| w.pattern.is_match(&line.text)) {
+                        w.notify.try_send(()).ok();
+                    }
+                    if let Ok(mut slot) = file.lock()
+                        && let Some(f) = slot.file.as_mut()
+                        && let Err(e) = writeln!(
+                            f,
+                            "{} {}",
+                            line.ts.to_rfc3339_opts(SecondsFormat::Millis, true),
+                            line.text
+                        )
+                    {
+                        warn!("{service}: writing {} log failed: {e}", stream.suffix());
+                    }
+                    if let Ok(mut ring) = tail.lock() {
+                        // Numbered under the lock, so the two streams never
+                        // hand out the same number.
+                        line.seq = ring.back().map_or(1, |last| last.seq + 1);
+                        if ring.len() == TAIL_CAPACITY {
+                            ring.pop_front();
+                        }
+                        ring.push_back(line);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            error!("{service}: cannot spawn {} reader: {e}", stream.suffix());
+        }
+    }
+}
+
+impl LiveFile {
+    fn closed() -> Self {
+        Self {
+            file: None,
+            opened: Utc::now(),
+        }
+    }
+
+    /// Wrap an opened file, dating it by its creation time when the
+    /// filesystem reports one so age survives daemon restarts.
+    fn open(file: File) -> Self {
+        let opened = file
+            .metadata()
+            .and_then(|m| m.created())
+            .map_or_else(|_| Utc::now(), DateTime::<Utc>::from);
+        Self {
+            file: Some(file),
+            opened,
+        }
+    }
+}
+
+/// `<path>.<ext>`
+fn append_ext(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// `<path>.<YYYYmmdd-HHMMSS>`, with a counter if that name is already taken.
+fn timestamped_name(path: &Path) -> PathBuf {
+    let stamp = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut candidate = append_ext(path, &stamp);
+    let mut n = 1;
+    while candidate.exists() || append_ext(&candidate, "gz").exists() {
+        candidate = append_ext(path, &format!("{stamp}-{n}"));
+        n += 1;
+    }
+    candidate
+}
+
+/// Shift `<path>.N[.gz]` to `<path>.N+1[.gz]` so `<path>.1` is free.
+fn shift_numbered(path: &Path, max_files: u32) -> io::Result<()> {
+    for n in (1..=max_files.max(1)).rev() {
+        for ext in ["", ".gz"] {
+            let from = append_ext(path, &format!("{n}{ext}"));
+            if from.exists() {
+                fs::rename(&from, append_ext(path, &format!("{}{ext}", n + 1)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compress `archive` into `archive.gz` and remove the original.
+fn gzip(archive: &Path) -> io::Result<PathBuf> {
+    let target = append_ext(archive, "gz");
+    let mut input = File::open(archive)?;
+    let mut encoder = GzEncoder::new(File::create(&target)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(archive)?;
+    Ok(target)
+}
+
+/// Delete the oldest archives of `path` beyond `max_files`.
+fn prune(path: &Path, max_files: u32) -> io::Result<()> {
+    let (Some(dir), Some(base)) = (path.parent(), path.file_name()) else {
+        return Ok(());
+    };
+    let mut prefix = base.to_owned();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let mut archives: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(dir)?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect();
+    archives.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    for (_, stale) in archives.into_iter().skip(max_files as usize) {
+        fs::remove_file(&stale)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn rotation(max_files: u32, compress: bool) -> LogRotationConfig {
+        LogRotationConfig {
+            max_size_mb: 1,
+            max_files,
+            interval_days: 0,
+            compress,
+            timestamp: false,
+        }
+    }
+
+    /// Append `bytes` to the live stdout file behind the capture's back.
+    fn grow(capture: &LogCapture, bytes: &[u8]) -> PathBuf {
+        let path = capture.path(LogStream::Stdout).expect("log path");
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(bytes))
+            .expect("append to live file");
+        path
+    }
+
+    #[test]
+    fn captures_both_streams() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let capture = LogCapture::new("capture", Some(dir.path()));
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("echo hello; echo oops >&2")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn sh");
+        capture.attach(&mut child, None);
+        child.wait().expect("wait for sh");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while capture.tail(10).len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let lines = capture.tail(10);
+        let seqs: Vec<u64> = lines.iter().map(|l| l.seq).collect();
+        assert_eq!(seqs, [1, 2]);
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.stream == LogStream::Stdout && l.text == "hello")
+        );
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.stream == LogStream::Stderr && l.text == "oops")
+        );
+        let out = fs::read_to_string(capture.path(LogStream::Stdout).expect("log path"))
+            .expect("stdout log");
+        assert!(out.ends_with(" hello\n"), "{out:?}");
+    }
+
+    #[test]
+    fn rotates_at_max_size() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let capture = LogCapture::new("size", Some(dir.path()));
+        let cfg = rotation(3, false);
+
+        let path = grow(&capture, &vec![b'a'; 512 * 1024]);
+        assert_eq!(capture.rotate(&cfg), 0);
+        grow(&capture, &vec![b'b'; 600 * 1024]);
+        assert_eq!(capture.rotate(&cfg), 1);
+
+        let archive = append_ext(&path, "1");
+        assert_eq!(fs::metadata(&archive).expect("archive").len(), 1112 * 1024);
+        assert_eq!(fs::metadata(&path).expect("fresh live file").len(), 0);
+    }
+
+    #[test]
+    fn prune_keeps_max_files() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("svc.out.log");
+        fs::write(&path, "live").expect("live file");
+        for n in 1..=5 {
+            fs::write(append_ext(&path, &n.to_string()), "old").expect("archive");
+        }
+        prune(&path, 2).expect("prune");
+
+        let archives = fs::read_dir(dir.path())
+            .expect("read dir")
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with("svc.out.log."))
+            .count();
+        assert_eq!(archives, 2);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn compressed_archive_round_trips() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let capture = LogCapture::new("gzip", Some(dir.path()));
+        let content: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let path = grow(&capture, &content);
+        assert_eq!(capture.rotate(&rotation(3, true)), 1);
+
+        let archive = append_ext(&append_ext(&path, "1"), "gz");
+        assert!(!append_ext(&path, "1").exists());
+        let mut restored = Vec::new();
+        GzDecoder::new(File::open(&archive).expect("archive"))
+            .read_to_end(&mut restored)
+            .expect("valid gzip");
+        assert_eq!(restored, content);
+    }
+}
@@ -0,0 +1,184 @@
+//! `memfs`: a RAM‑backed scratch area per service run.
+//!
+//! Where the daemon may mount (root, or its own mount namespace) this is a
+//! real tmpfs of `size_mb` under `/run/kodegend/memfs`.  Otherwise it falls
+//! back to a plain directory – there for root, in the daemon's private
+//! `kodegend-<uid>` directory under `/dev/shm` for anyone else – whose size
+//! is checked on every health tick instead of being enforced by the kernel.
+//!
+//! The child finds the path in `KODEGEN_MEMFS_DIR`.  Dropping a [`MemFs`]
+//! unmounts and removes it, so a stop, a crash and a worker exit all clean up.
+
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use nix::unistd::{Gid, Uid};
+
+use super::ephemeral;
+use crate::config::MemoryFsConfig;
+
+/// Upper bound for `size_mb`.
+pub const MAX_SIZE_MB: u32 = 2048;
+
+/// Environment variable carrying the mount point.
+pub const ENV_VAR: &str = "KODEGEN_MEMFS_DIR";
+
+/// Parent of the tmpfs mount points.
+const MOUNT_BASE: &str = "/run/kodegend/memfs";
+
+/// Parent of the unprivileged fallback directories.
+const SHM_BASE: &str = "/dev/shm";
+
+#[derive(Debug)]
+pub struct MemFs {
+    path: PathBuf,
+    mounted: bool,
+    limit_mb: u32,
+}
+
+impl MemFs {
+    /// Create the scratch area described by `cfg`, owned by `owner`.
+    pub fn create(
+        service: &str,
+        cfg: &MemoryFsConfig,
+        owner: Option<(Option<Uid>, Gid)>,
+    ) -> io::Result<Self> {
+        let name = cfg.mount_name.as_str();
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid memfs mount_name '{name}'"),
+            ));
+        }
+        let limit_mb = cfg.size_mb.clamp(1, MAX_SIZE_MB);
+        if limit_mb != cfg.size_mb {
+            warn!(
+                "{service}: memfs size_mb {} clamped to {limit_mb}",
+                cfg.size_mb
+            );
+        }
+        let (uid, gid) = match owner {
+            Some((uid, gid)) => (uid.unwrap_or_else(Uid::effective), gid),
+            None => (Uid::effective(), Gid::effective()),
+        };
+
+        #[cfg(target_os = "linux")]
+        if Uid::effective().is_root() {
+            let path = Path::new(MOUNT_BASE).join(name);
+            match mount_tmpfs(&path, limit_mb, uid, gid) {
+                Ok(()) => {
+                    return Ok(Self {
+                        path,
+                        mounted: true,
+                        limit_mb,
+                    });
+                }
+                Err(e) => warn!(
+                    "{service}: cannot mount tmpfs at {}: {e} – using a plain directory",
+                    path.display()
+                ),
+            }
+        }
+
+        // Root keeps the directory below its own `/run`, which service
+        // users can traverse; anyone else below its private directory in
+        // the world-writable `/dev/shm`.
+        let path = if cfg!(target_os = "linux") && Uid::effective().is_root() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o755)
+                .create(MOUNT_BASE)?;
+            Path::new(MOUNT_BASE).join(name)
+        } else {
+            let shared = if Path::new(SHM_BASE).is_dir() {
+                PathBuf::from(SHM_BASE)
+            } else {
+                std::env::temp_dir()
+            };
+            ephemeral::private_base(&shared)?.join(name)
+        };
+        ephemeral::wipe(&path)?;
+        DirBuilder::new().mode(0o700).create(&path)?;
+        ephemeral::hand_over(&path, Some(uid), gid);
+        Ok(Self {
+            path,
+            mounted: false,
+            limit_mb,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Megabytes in use, if a fallback directory has outgrown its limit.
+    /// A tmpfs is capped by the kernel and never reports.
+    #[must_use]
+    pub fn over_limit(&self) -> Option<u64> {
+        if self.mounted {
+            return None;
+        }
+        let used_mb = disk_usage(&self.path) / (1024 * 1024);
+        (used_mb > u64::from(self.limit_mb)).then_some(used_mb)
+    }
+
+    #[must_use]
+    pub fn limit_mb(&self) -> u32 {
+        self.limit_mb
+    }
+}
+
+impl Drop for MemFs {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if self.mounted
+            && let Err(e) = nix::mount::umount2(&self.path, nix::mount::MntFlags::MNT_DETACH)
+        {
+            warn!("cannot unmount memfs {}: {e}", self.path.display());
+        }
+        if let Err(e) = ephemeral::wipe(&self.path) {
+            warn!("cannot remove memfs {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Mount a fresh tmpfs at `path`, replacing one a crashed daemon left.
+#[cfg(target_os = "linux")]
+fn mount_tmpfs(path: &Path, size_mb: u32, uid: Uid, gid: Gid) -> io::Result<()> {
+    use nix::mount::{MntFlags, MsFlags, mount, umount2};
+
+    DirBuilder::new().recursive(true).mode(0o755).create(path)?;
+    umount2(path, MntFlags::MNT_DETACH).ok();
+    let opts = format!("size={size_mb}m,mode=0700,uid={uid},gid={gid}");
+    mount(
+        Some("tmpfs"),
+        path,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(opts.as_str()),
+    )?;
+    Ok(())
+}
+
+/// Bytes used by the files below `dir` (symlinks are not followed).
+fn disk_usage(dir: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => pending.push(entry.path()),
+                Ok(meta) => total += meta.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    total
+}
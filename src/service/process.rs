@@ -0,0 +1,116 @@
+//! Process‑group handling for supervised children.
+//!
+//! Every child is made the leader of its own process group at spawn, so a
+//! stop reaches the `sh -c` wrapper *and* everything it forked.
+//!
+//! A waiter thread per child notices the exit the moment it happens.  It
+//! waits with `WNOWAIT`, so the zombie stays in place and the worker's
+//! `Child` remains the only thing that ever reaps it.
+
+use std::io;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use log::warn;
+use nix::errno::Errno;
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+
+use crate::ipc::ExitInfo;
+
+/// Grace period between the stop signal and SIGKILL when the service does
+/// not set `stop_timeout_s`.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the group is polled while waiting for it to exit.
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+/// Parse a signal name as written in a service file: `SIGTERM`, `TERM`,
+/// `term` or a plain number such as `15`.
+#[must_use]
+pub fn parse_signal(raw: &str) -> Option<Signal> {
+    let raw = raw.trim();
+    if let Ok(num) = raw.parse::<i32>() {
+        return Signal::try_from(num).ok();
+    }
+    let upper = raw.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{upper}")
+    };
+    name.parse().ok()
+}
+
+/// Send `signal` to the child's process group, wait up to `timeout` for the
+/// whole group to exit, then SIGKILL whatever is left.
+///
+/// Returns the leader's exit status, or `None` if it could not be reaped.
+pub fn terminate_group(child: &mut Child, signal: Signal, timeout: Duration) -> Option<ExitStatus> {
+    let pgid = Pid::from_raw(child.id() as i32);
+    let mut status = child.try_wait().ok().flatten();
+
+    match killpg(pgid, signal) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(e) => warn!("killpg({pgid}, {signal}) failed: {e}"),
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if status.is_none() {
+            status = child.try_wait().ok().flatten();
+        }
+        // Signal 0 only probes whether any group member is still alive.
+        let group_alive = killpg(pgid, None).is_ok();
+        if status.is_some() && !group_alive {
+            return status;
+        }
+        if Instant::now() >= deadline {
+            warn!("process group {pgid} still alive after {timeout:?} – sending SIGKILL");
+            killpg(pgid, Signal::SIGKILL).ok();
+            return status.or_else(|| child.wait().ok());
+        }
+        thread::sleep(STOP_POLL);
+    }
+}
+
+/// Send `pid` on `notify` once that child has exited.  The child is left
+/// unreaped; the receiver collects its status with `Child::try_wait`.
+pub fn watch_exit(pid: u32, notify: Sender<u32>) -> io::Result<()> {
+    thread::Builder::new()
+        .name(format!("wait-{pid}"))
+        .spawn(move || {
+            loop {
+                // SAFETY: `info` is a plain C struct the kernel fills in.
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                let rc = unsafe {
+                    libc::waitid(
+                        libc::P_PID,
+                        pid as libc::id_t,
+                        &mut info,
+                        libc::WEXITED | libc::WNOWAIT,
+                    )
+                };
+                if rc == 0 || Errno::last() != Errno::EINTR {
+                    break;
+                }
+            }
+            // ECHILD means the worker already reaped it during a stop.
+            notify.send(pid).ok();
+        })
+        .map(drop)
+}
+
+impl From<ExitStatus> for ExitInfo {
+    fn from(status: ExitStatus) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+            core_dumped: status.core_dumped(),
+        }
+    }
+}
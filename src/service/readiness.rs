@@ -0,0 +1,192 @@
+//! `readiness`: when a started service counts as running.
+//!
+//! * `exec` – as soon as the process is spawned (the default).
+//! * `notify` – once the child sends `READY=1` to the datagram socket named
+//!   in `NOTIFY_SOCKET`, as with systemd's `sd_notify(3)`.
+//! * `tcp-port` – once `host:port` accepts a connection.
+//! * `log-line` – once a stdout line matches `pattern`.
+//!
+//! A service that is not ready within `timeout_s` is stopped and its start
+//! counts as failed.
+
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Sender, bounded};
+use log::{debug, warn};
+use nix::unistd::{Gid, Uid};
+use regex::Regex;
+use tempfile::TempDir;
+
+use super::ephemeral;
+use crate::config::{ReadinessConfig, ReadinessMode};
+
+/// Environment variable carrying the notification socket path.
+pub const NOTIFY_ENV: &str = "NOTIFY_SOCKET";
+
+/// How often `tcp-port` tries to connect.
+pub const TCP_POLL: Duration = Duration::from_millis(250);
+
+/// Connect timeout of a single `tcp-port` attempt.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Parent of the notification sockets of a root daemon.
+const NOTIFY_BASE: &str = "/run/kodegend/notify";
+
+/// A service's readiness check, validated before the process is spawned.
+#[derive(Debug)]
+pub enum Readiness {
+    Exec,
+    Notify,
+    TcpPort { host: String, port: u16 },
+    LogLine(Regex),
+}
+
+impl Readiness {
+    pub fn new(cfg: &ReadinessConfig) -> Result<Self, String> {
+        Ok(match cfg.mode {
+            ReadinessMode::Exec => Self::Exec,
+            ReadinessMode::Notify => Self::Notify,
+            ReadinessMode::TcpPort => Self::TcpPort {
+                host: cfg.host.clone().unwrap_or_else(|| "127.0.0.1".to_string()),
+                port: cfg.port.ok_or("tcp-port readiness needs a port")?,
+            },
+            ReadinessMode::LogLine => {
+                let pattern = cfg
+                    .pattern
+                    .as_deref()
+                    .ok_or("log-line readiness needs a pattern")?;
+                Self::LogLine(
+                    Regex::new(pattern).map_err(|e| format!("invalid readiness pattern: {e}"))?,
+                )
+            }
+        })
+    }
+}
+
+/// Whether `host:port` accepts connections right now.
+#[must_use]
+pub fn tcp_ready(host: &str, port: u16) -> bool {
+    let Ok(addrs) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT).is_ok())
+}
+
+/// The `NOTIFY_SOCKET` of one service run; removed on drop.
+#[derive(Debug)]
+pub struct NotifySocket {
+    path: PathBuf,
+    socket: UnixDatagram,
+    /// Private directory holding the socket when the usual one failed.
+    _fallback: Option<TempDir>,
+}
+
+impl NotifySocket {
+    /// Bind the socket for `service`, writable by `owner`.  The receiver gets
+    /// a ping when the child reports `READY=1`.
+    pub fn bind(
+        service: &str,
+        owner: Option<(Option<Uid>, Gid)>,
+    ) -> io::Result<(Self, Receiver<()>)> {
+        let (path, socket, fallback) = match bind_shared(service) {
+            Ok((path, socket)) => (path, socket, None),
+            Err(e) => {
+                warn!("{service}: cannot bind notify socket: {e} – using a private directory");
+                let dir = tempfile::Builder::new()
+                    .prefix("kodegend-notify-")
+                    .tempdir()?;
+                if let Some((uid, gid)) = owner {
+                    ephemeral::hand_over(dir.path(), uid, gid);
+                }
+                let path = dir.path().join(format!("{service}.notify"));
+                (path.clone(), UnixDatagram::bind(&path)?, Some(dir))
+            }
+        };
+        if let Some((uid, gid)) = owner {
+            ephemeral::hand_over(&path, uid, gid);
+        }
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        let (tx, rx) = bounded(1);
+        let reader = socket.try_clone()?;
+        let name = service.to_string();
+        thread::Builder::new()
+            .name(format!("notify-{service}"))
+            .spawn(move || read_notifications(&name, &reader, &tx))?;
+        Ok((
+            Self {
+                path,
+                socket,
+                _fallback: fallback,
+            },
+            rx,
+        ))
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        // Wakes the reader thread, which then exits.
+        self.socket.shutdown(std::net::Shutdown::Both).ok();
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("cannot remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Bind below `/run/kodegend/notify` for root, below the daemon's private
+/// directory in the temporary directory otherwise.
+fn bind_shared(service: &str) -> io::Result<(PathBuf, UnixDatagram)> {
+    let dir = if Uid::effective().is_root() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(NOTIFY_BASE)?;
+        PathBuf::from(NOTIFY_BASE)
+    } else {
+        ephemeral::private_base(&std::env::temp_dir())?
+    };
+    let path = dir.join(format!("{service}.notify"));
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let socket = UnixDatagram::bind(&path)?;
+    Ok((path, socket))
+}
+
+fn read_notifications(service: &str, socket: &UnixDatagram, ready: &Sender<()>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        for line in String::from_utf8_lossy(&buf[..len]).lines() {
+            match line.split_once('=') {
+                Some(("READY", "1")) => {
+                    ready.try_send(()).ok();
+                }
+                Some(("STATUS", status)) => debug!("{service}: {status}"),
+                _ => {}
+            }
+        }
+    }
+}
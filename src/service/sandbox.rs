@@ -0,0 +1,476 @@
+//! `sandbox`: confine a service with namespaces and a seccomp filter.
+//!
+//! The worker prepares everything up front – mount table, writable paths,
+//! BPF program – so the `pre_exec` hooks only issue raw syscalls.  The
+//! namespaces are entered before the child drops its privileges, the filter
+//! is installed after, right before exec.
+//!
+//! With `private_pid` the forked child stays behind as a plain waiter and
+//! forks the namespace's init, which forks the process that execs the
+//! service.  All three share the service's process group, so a stop signal
+//! reaches the service itself; the waiter ignores it and exits with the
+//! service's status once init has collected it.
+
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use thiserror::Error;
+#[cfg(target_os = "linux")]
+use {
+    super::seccomp, nix::unistd::Uid, std::ffi::CString, std::fs, std::os::unix::ffi::OsStringExt,
+    std::os::unix::process::CommandExt, std::path::PathBuf, std::ptr,
+};
+
+use crate::config::SandboxConfig;
+
+/// Mounts left as they are under `read_only_root`: the kernel interfaces a
+/// process needs to write to.
+#[cfg(target_os = "linux")]
+const KEEP_WRITABLE: [&str; 2] = ["/proc", "/dev"];
+
+/// Signals the waiter ignores; they are meant for the service.
+#[cfg(target_os = "linux")]
+const FORWARDED: [libc::c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+#[derive(Error, Debug)]
+pub enum SandboxError {
+    #[error("namespace options need a root daemon")]
+    NotRoot,
+
+    #[error("unknown seccomp profile '{0}' (expected one of: default, no-network)")]
+    UnknownProfile(String),
+
+    #[error("read_write path '{path}': {source}")]
+    Path {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("read_write path '{0}' lies under /tmp, which private_tmp replaces")]
+    HiddenByTmp(String),
+
+    #[error("cannot read the mount table: {0}")]
+    MountTable(#[source] io::Error),
+
+    #[error(
+        "the daemon's own confinement forbids namespaces ({0}); the systemd unit only allows \
+         them if a service had `sandbox` at install time – reinstall the daemon"
+    )]
+    Confined(#[source] io::Error),
+
+    #[cfg(not(target_os = "linux"))]
+    #[error("sandbox needs Linux namespaces and seccomp")]
+    Unsupported,
+}
+
+/// A prepared sandbox, ready to be attached to a `Command`.
+#[derive(Debug)]
+pub struct Sandbox {
+    #[cfg(target_os = "linux")]
+    namespaces: Option<Namespaces>,
+    #[cfg(target_os = "linux")]
+    seccomp: Option<Vec<libc::sock_filter>>,
+}
+
+/// What the child sets up right after `unshare(2)`.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+struct Namespaces {
+    private_tmp: bool,
+    no_network: bool,
+    private_pid: bool,
+    /// Bound onto themselves before the rest turns read‑only.
+    read_write: Vec<CString>,
+    /// Mount points to remount read‑only, with the flags they keep.
+    read_only: Vec<(CString, libc::c_ulong)>,
+    /// Upper bound for descriptors the waiters close.
+    max_fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl Sandbox {
+    /// Prepare `cfg`; `extra_rw` are per‑run paths that stay writable.
+    pub fn prepare(cfg: &SandboxConfig, extra_rw: &[&Path]) -> Result<Self, SandboxError> {
+        let seccomp = cfg
+            .seccomp_profile
+            .as_deref()
+            .map(|name| {
+                seccomp::profile(name).ok_or_else(|| SandboxError::UnknownProfile(name.into()))
+            })
+            .transpose()?;
+
+        let wants_namespaces =
+            cfg.private_tmp || cfg.read_only_root || cfg.no_network || cfg.private_pid;
+        if !wants_namespaces {
+            return Ok(Self {
+                namespaces: None,
+                seccomp,
+            });
+        }
+        if !Uid::effective().is_root() {
+            return Err(SandboxError::NotRoot);
+        }
+        let mut flags = libc::CLONE_NEWNS;
+        if cfg.no_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if cfg.private_pid {
+            flags |= libc::CLONE_NEWPID;
+        }
+        probe(flags).map_err(SandboxError::Confined)?;
+
+        let mut read_write = Vec::new();
+        let mut read_only = Vec::new();
+        if cfg.read_only_root {
+            let mut keep = Vec::new();
+            let configured = cfg.read_write_paths.iter().map(Path::new);
+            for path in configured.chain(extra_rw.iter().copied()) {
+                let display = path.display().to_string();
+                let path = fs::canonicalize(path).map_err(|source| SandboxError::Path {
+                    path: display.clone(),
+                    source,
+                })?;
+                if cfg.private_tmp && path.starts_with("/tmp") {
+                    return Err(SandboxError::HiddenByTmp(display));
+                }
+                keep.push(path);
+            }
+            read_only = read_only_mounts(&keep, cfg.private_tmp)?;
+            read_write = keep.into_iter().map(c_path).collect();
+        }
+
+        // SAFETY: `limit` is a plain C struct the kernel fills in.
+        let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+        let max_fd = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+            limit.rlim_cur.min(1 << 20) as libc::c_int
+        } else {
+            1024
+        };
+
+        Ok(Self {
+            namespaces: Some(Namespaces {
+                private_tmp: cfg.private_tmp,
+                no_network: cfg.no_network,
+                private_pid: cfg.private_pid,
+                read_write,
+                read_only,
+                max_fd,
+            }),
+            seccomp,
+        })
+    }
+
+    /// Enter the namespaces between fork and exec.  Register before the
+    /// hook that drops privileges.
+    pub fn confine(&self, cmd: &mut Command) {
+        let Some(ns) = self.namespaces.clone() else {
+            return;
+        };
+        // SAFETY: `enter` only issues async‑signal‑safe syscalls on data
+        // prepared before the fork.
+        unsafe {
+            cmd.pre_exec(move || ns.enter());
+        }
+    }
+
+    /// Install the seccomp filter; register as the last hook.
+    pub fn restrict(&self, cmd: &mut Command) {
+        let Some(prog) = self.seccomp.clone() else {
+            return;
+        };
+        // SAFETY: `install` only issues two `prctl(2)` calls.
+        unsafe {
+            cmd.pre_exec(move || seccomp::install(&prog));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Sandbox {
+    pub fn prepare(_cfg: &SandboxConfig, _extra_rw: &[&Path]) -> Result<Self, SandboxError> {
+        Err(SandboxError::Unsupported)
+    }
+
+    pub fn confine(&self, _cmd: &mut Command) {}
+
+    pub fn restrict(&self, _cmd: &mut Command) {}
+}
+
+#[cfg(target_os = "linux")]
+impl Namespaces {
+    fn enter(&self) -> io::Result<()> {
+        let mut flags = libc::CLONE_NEWNS;
+        if self.no_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if self.private_pid {
+            flags |= libc::CLONE_NEWPID;
+        }
+        // SAFETY: raw syscalls on C strings owned by `self`.
+        unsafe {
+            check(libc::unshare(flags))?;
+            // Nothing mounted from here on may propagate back to the host.
+            check(libc::mount(
+                ptr::null(),
+                c"/".as_ptr(),
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+            if self.private_tmp {
+                check(libc::mount(
+                    c"tmpfs".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    c"mode=1777".as_ptr().cast(),
+                ))?;
+            }
+            for path in &self.read_write {
+                check(libc::mount(
+                    path.as_ptr(),
+                    path.as_ptr(),
+                    ptr::null(),
+                    libc::MS_BIND | libc::MS_REC,
+                    ptr::null(),
+                ))?;
+            }
+            for (target, keep) in &self.read_only {
+                let rc = libc::mount(
+                    ptr::null(),
+                    target.as_ptr(),
+                    ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | keep,
+                    ptr::null(),
+                );
+                // Gone since the table was read – nothing left to protect.
+                if rc != 0
+                    && !matches!(
+                        io::Error::last_os_error().raw_os_error(),
+                        Some(libc::ENOENT | libc::EINVAL)
+                    )
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if self.no_network {
+                loopback_up()?;
+            }
+            if self.private_pid {
+                self.spawn_init()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fork the PID namespace's init, which forks the process that goes on
+    /// to exec the service.  Only that last process returns.
+    unsafe fn spawn_init(&self) -> io::Result<()> {
+        unsafe {
+            let init = check(libc::fork())?;
+            if init > 0 {
+                for sig in FORWARDED {
+                    libc::signal(sig, libc::SIG_IGN);
+                }
+                close_cloexec(self.max_fd);
+                libc::_exit(wait_exit(init));
+            }
+            check(libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                ptr::null(),
+            ))?;
+            let service = check(libc::fork())?;
+            if service > 0 {
+                close_cloexec(self.max_fd);
+                // Reaps every orphan in the namespace along the way.
+                libc::_exit(wait_exit(service));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Try `unshare(flags)` and a private remount in a throwaway child, so a
+/// daemon confined by its service manager fails the start with a clear
+/// error rather than a bare `EPERM` from the `pre_exec` hook.
+#[cfg(target_os = "linux")]
+fn probe(flags: libc::c_int) -> io::Result<()> {
+    // SAFETY: the child only issues async‑signal‑safe syscalls and exits.
+    unsafe {
+        let pid = check(libc::fork())?;
+        if pid == 0 {
+            let rc = if libc::unshare(flags) == 0
+                && libc::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ) == 0
+            {
+                0
+            } else {
+                *libc::__errno_location()
+            };
+            libc::_exit(rc);
+        }
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        match libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)) {
+            Some(0) => Ok(()),
+            Some(errno) => Err(io::Error::from_raw_os_error(errno)),
+            None => Err(io::Error::other("namespace probe was killed")),
+        }
+    }
+}
+
+/// Mount points to make read‑only: everything not already read‑only, kept
+/// writable, hidden by `private_tmp` or below a `keep` path.
+#[cfg(target_os = "linux")]
+fn read_only_mounts(
+    keep: &[PathBuf],
+    private_tmp: bool,
+) -> Result<Vec<(CString, libc::c_ulong)>, SandboxError> {
+    let table = fs::read_to_string("/proc/self/mountinfo").map_err(SandboxError::MountTable)?;
+    let mut mounts = Vec::new();
+    for line in table.lines() {
+        // id parent major:minor root mount_point options …
+        let mut fields = line.split(' ').skip(4);
+        let (Some(mount_point), Some(options)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let mount_point = PathBuf::from(unescape(mount_point));
+        let mut flags = 0;
+        for option in options.split(',') {
+            flags |= match option {
+                "ro" => libc::MS_RDONLY,
+                "nosuid" => libc::MS_NOSUID,
+                "nodev" => libc::MS_NODEV,
+                "noexec" => libc::MS_NOEXEC,
+                "noatime" => libc::MS_NOATIME,
+                "nodiratime" => libc::MS_NODIRATIME,
+                "relatime" => libc::MS_RELATIME,
+                _ => 0,
+            };
+        }
+        let skip = flags & libc::MS_RDONLY != 0
+            || KEEP_WRITABLE.iter().any(|p| mount_point.starts_with(p))
+            || (private_tmp && mount_point.starts_with("/tmp"))
+            || keep.iter().any(|p| mount_point.starts_with(p));
+        if !skip {
+            mounts.push((c_path(mount_point), flags));
+        }
+    }
+    Ok(mounts)
+}
+
+/// Undo the octal escapes (`\040` for a space) of `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+fn unescape(raw: &str) -> std::ffi::OsString {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|d| bytes[i] == b'\\' && d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match octal {
+            Some(d) => {
+                out.push((d[0] - b'0') << 6 | (d[1] - b'0') << 3 | (d[2] - b'0'));
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    std::ffi::OsString::from_vec(out)
+}
+
+#[cfg(target_os = "linux")]
+fn c_path(path: PathBuf) -> CString {
+    // Paths from the kernel or `canonicalize` never contain NUL.
+    CString::new(path.into_os_string().into_vec()).unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn check(rc: libc::c_int) -> io::Result<libc::c_int> {
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(rc)
+    }
+}
+
+/// Bring up `lo` in a fresh network namespace.
+#[cfg(target_os = "linux")]
+unsafe fn loopback_up() -> io::Result<()> {
+    unsafe {
+        let fd = check(libc::socket(
+            libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            0,
+        ))?;
+        let mut req: libc::ifreq = std::mem::zeroed();
+        req.ifr_name[0] = b'l' as libc::c_char;
+        req.ifr_name[1] = b'o' as libc::c_char;
+        let mut rc = libc::ioctl(fd, libc::SIOCGIFFLAGS as _, &mut req);
+        if rc == 0 {
+            req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            rc = libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &req);
+        }
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if rc != 0 { Err(err) } else { Ok(()) }
+    }
+}
+
+/// Wait until `pid` exits, reaping any other child on the way, and turn
+/// its status into an exit code (`128 + signal` like a shell).
+#[cfg(target_os = "linux")]
+unsafe fn wait_exit(pid: libc::pid_t) -> libc::c_int {
+    loop {
+        let mut status = 0;
+        let rc = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if rc == pid {
+            return if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                128 + libc::WTERMSIG(status)
+            };
+        }
+        if rc < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            return 1;
+        }
+    }
+}
+
+/// Close every close‑on‑exec descriptor.  The waiters never exec, so they
+/// would otherwise hold `Command::spawn`'s error pipe open and keep it
+/// waiting until the service ends.
+#[cfg(target_os = "linux")]
+unsafe fn close_cloexec(max_fd: libc::c_int) {
+    for fd in 3..max_fd {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags >= 0 && flags & libc::FD_CLOEXEC != 0 {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! `schedule` of a `scheduled` service.
+//!
+//! Either a five‑field cron expression – `minute hour day-of-month month
+//! day-of-week`, evaluated in local time, with `*`, lists, ranges, `/step`
+//! and three‑letter month and weekday names – one of the shorthands
+//! `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`, or a fixed
+//! interval such as `@every 15m` (units `s`, `m`, `h`, `d`).
+//!
+//! As in cron, when both day fields are restricted a day matching either of
+//! them is enough.
+
+use std::time::Duration;
+
+use chrono::{
+    DateTime, Datelike, Local, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+};
+use thiserror::Error;
+
+/// How far ahead to look for a matching time before giving up (`30 2 31 2 *`
+/// never fires).
+const MAX_YEARS_AHEAD: i32 = 5;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    #[error("expected 5 cron fields, found {0}")]
+    FieldCount(usize),
+
+    #[error("invalid {field} field '{value}'")]
+    Field { field: &'static str, value: String },
+
+    #[error("invalid interval '{0}' (expected e.g. 90s, 15m, 6h or 1d)")]
+    Interval(String),
+
+    #[error("unknown shorthand '{0}'")]
+    Shorthand(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Cron(Cron),
+    Every(Duration),
+}
+
+/// One bit per allowed value of each field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether the day fields were `*`, which changes how they combine.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(raw: &str) -> Result<Self, ScheduleError> {
+        let raw = raw.trim();
+        let expr = match raw {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ => match raw.strip_prefix("@every") {
+                Some(interval) => return parse_interval(interval.trim()).map(Self::Every),
+                None if raw.starts_with('@') => {
+                    return Err(ScheduleError::Shorthand(raw.to_string()));
+                }
+                None => raw,
+            },
+        };
+        Cron::parse(expr).map(Self::Cron)
+    }
+
+    /// The first run strictly after `now`, or `None` if there is none within
+    /// the next few years.
+    #[must_use]
+    pub fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Self::Every(interval) => Some(now + *interval),
+            Self::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Self, ScheduleError> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(ScheduleError::FieldCount(fields.len()));
+        };
+        // Sunday may be written as 7 as well as 0.
+        let weekdays = parse_field("day-of-week", weekday, 0, 7, &WEEKDAYS, 0)?;
+        Ok(Self {
+            minutes: parse_field("minute", minute, 0, 59, &[], 0)?,
+            hours: parse_field("hour", hour, 0, 23, &[], 0)? as u32,
+            days: parse_field("day-of-month", day, 1, 31, &[], 0)? as u32,
+            months: parse_field("month", month, 1, 12, &MONTHS, 1)? as u16,
+            weekdays: ((weekdays | weekdays >> 7) & 0x7f) as u8,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut t =
+            now.naive_local().with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        let give_up = now.year() + MAX_YEARS_AHEAD;
+        while t.year() <= give_up {
+            if self.months & 1 << t.month() == 0 {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << t.hour() == 0 {
+                t = t.with_minute(0)? + chrono::Duration::hours(1);
+            } else if self.minutes & 1 << t.minute() == 0 {
+                t += chrono::Duration::minutes(1);
+            } else {
+                match Local.from_local_datetime(&t) {
+                    LocalResult::Single(at) | LocalResult::Ambiguous(at, _) => return Some(at),
+                    // Skipped by a DST change.
+                    LocalResult::None => t += chrono::Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, t: &NaiveDateTime) -> bool {
+        let day = self.days & 1 << t.day() != 0;
+        let weekday = self.weekdays & 1 << t.weekday().num_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+/// Parse one cron field into a bitmask.  `names[i]` stands for
+/// `i + name_base`.
+fn parse_field(
+    field: &'static str,
+    value: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    name_base: u32,
+) -> Result<u64, ScheduleError> {
+    let invalid = || ScheduleError::Field {
+        field,
+        value: value.to_string(),
+    };
+    let number = |raw: &str| -> Result<u32, ScheduleError> {
+        let lower = raw.to_ascii_lowercase();
+        let n = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + name_base,
+            None => raw.parse().map_err(|_| invalid())?,
+        };
+        if (min..=max).contains(&n) {
+            Ok(n)
+        } else {
+            Err(invalid())
+        }
+    };
+
+    let mut mask = 0u64;
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (number(a)?, number(b)?),
+                // `5/15` means from 5 to the end in steps of 15.
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let n = number(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for n in (first..=last).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_interval(raw: &str) -> Result<Duration, ScheduleError> {
+    let invalid = || ScheduleError::Interval(raw.to_string());
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = raw.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(invalid()),
+    };
+    if amount == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(amount * secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(y, mo, d, h, mi, 0)
+            .earliest()
+            .expect("valid local time")
+    }
+
+    #[test]
+    fn parses_cron_shorthands_and_intervals() {
+        assert_eq!(
+            Schedule::parse("@every 15m"),
+            Ok(Schedule::Every(Duration::from_secs(900)))
+        );
+        assert_eq!(Schedule::parse("@daily"), Schedule::parse("0 0 * * *"));
+        assert_eq!(
+            Schedule::parse("0 3 * * mon-fri"),
+            Schedule::parse("0 3 * * 1-5")
+        );
+        assert_eq!(Schedule::parse("0 0 * * 7"), Schedule::parse("0 0 * * 0"));
+        assert!(matches!(
+            Schedule::parse("0 24 * * *"),
+            Err(ScheduleError::Field { field: "hour", .. })
+        ));
+        assert_eq!(Schedule::parse("* * *"), Err(ScheduleError::FieldCount(3)));
+        assert!(Schedule::parse("@every 0s").is_err());
+        assert!(Schedule::parse("@sometimes").is_err());
+    }
+
+    #[test]
+    fn finds_next_run() {
+        let nightly = Schedule::parse("30 3 * * *").expect("valid");
+        assert_eq!(
+            nightly.next_after(at(2025, 6, 10, 12, 0)),
+            Some(at(2025, 6, 11, 3, 30))
+        );
+        // Strictly after: a job due right now runs next time.
+        assert_eq!(
+            nightly.next_after(at(2025, 6, 11, 3, 30)),
+            Some(at(2025, 6, 12, 3, 30))
+        );
+
+        let quarter = Schedule::parse("*/15 9-17 * * *").expect("valid");
+        assert_eq!(
+            quarter.next_after(at(2025, 6, 10, 17, 50)),
+            Some(at(2025, 6, 11, 9, 0))
+        );
+
+        // Day of month *or* Monday.
+        let either = Schedule::parse("0 0 13 * mon").expect("valid");
+        assert_eq!(
+            either.next_after(at(2025, 6, 10, 0, 0)),
+            Some(at(2025, 6, 13, 0, 0))
+        );
+        assert_eq!(
+            either.next_after(at(2025, 6, 13, 0, 0)),
+            Some(at(2025, 6, 16, 0, 0))
+        );
+
+        let never = Schedule::parse("0 0 30 2 *").expect("valid");
+        assert_eq!(never.next_after(at(2025, 1, 1, 0, 0)), None);
+    }
+}
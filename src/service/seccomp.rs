@@ -0,0 +1,191 @@
+//! Named seccomp filter profiles for `sandbox.seccomp_profile`.
+//!
+//! * `default` – refuses the syscalls that administer the host rather than
+//!   the process: mounts, module loading, reboot and kexec, swap, clocks,
+//!   ptrace, bpf, keyrings and namespace changes – including a `clone` that
+//!   asks for new namespaces – as well as io_uring, whose requests bypass
+//!   the filter.
+//! * `no-network` – `default`, plus every socket that is not a Unix socket.
+//!
+//! Refused calls fail with `EPERM`.  `clone3` fails with `ENOSYS` instead:
+//! its flags live in memory the filter cannot read, and libc falls back to
+//! plain `clone` on that error only.  A syscall made through a foreign ABI
+//! (32‑bit or x32 on x86_64) kills the process, since the numbers checked
+//! below would not mean the same thing there.
+
+use std::io;
+
+use libc::sock_filter;
+
+// `AUDIT_ARCH_*` from <linux/audit.h>; libc does not export them.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// Syscall numbers at or above this are the x32 ABI.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// Offsets into `struct seccomp_data`.
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+const OFFSET_ARG0: u32 = 16;
+
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+];
+
+/// `clone` flags that create namespaces.
+const NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET;
+
+/// The BPF program for profile `name`, or `None` if there is no such profile.
+#[must_use]
+pub fn profile(name: &str) -> Option<Vec<sock_filter>> {
+    let no_network = match name {
+        "default" => false,
+        "no-network" => true,
+        _ => return None,
+    };
+    let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+
+    let mut prog = vec![
+        load(OFFSET_ARCH),
+        jump_eq(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(OFFSET_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    prog.extend([
+        jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+    for &nr in DENIED {
+        prog.extend([jump_eq(nr as u32, 0, 1), ret(deny)]);
+    }
+    prog.extend([
+        jump_eq(libc::SYS_clone3 as u32, 0, 1),
+        ret(libc::SECCOMP_RET_ERRNO | (libc::ENOSYS as u32 & libc::SECCOMP_RET_DATA)),
+        // clone(flags, …): not a clone call → skip the flag check.
+        jump_eq(libc::SYS_clone as u32, 0, 4),
+        load(OFFSET_ARG0),
+        jump(libc::BPF_JSET, NAMESPACE_FLAGS as u32, 0, 1),
+        ret(deny),
+        ret(libc::SECCOMP_RET_ALLOW),
+    ]);
+    if no_network {
+        // socket(domain, …): not a socket call → skip the domain check.
+        prog.extend([
+            jump_eq(libc::SYS_socket as u32, 0, 4),
+            load(OFFSET_ARG0),
+            jump_eq(libc::AF_UNIX as u32, 0, 1),
+            ret(libc::SECCOMP_RET_ALLOW),
+            ret(deny),
+        ]);
+    }
+    prog.push(ret(libc::SECCOMP_RET_ALLOW));
+    Some(prog)
+}
+
+/// Install `prog` for the calling thread and everything it execs.
+///
+/// Async‑signal‑safe: meant for a `pre_exec` hook.
+pub fn install(prog: &[sock_filter]) -> io::Result<()> {
+    let fprog = libc::sock_fprog {
+        len: prog.len() as libc::c_ushort,
+        filter: prog.as_ptr().cast_mut(),
+    };
+    // SAFETY: `fprog` points at `prog`, which outlives both calls.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &fprog as *const libc::sock_fprog,
+            ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn load(offset: u32) -> sock_filter {
+    stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset)
+}
+
+fn ret(value: u32) -> sock_filter {
+    stmt(libc::BPF_RET | libc::BPF_K, value)
+}
+
+fn jump_eq(value: u32, jt: u8, jf: u8) -> sock_filter {
+    jump(libc::BPF_JEQ, value, jt, jf)
+}
+
+fn jump(op: u32, value: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k: value,
+    }
+}
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
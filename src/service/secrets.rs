@@ -0,0 +1,259 @@
+//! `env_files` and secret references, resolved right before each spawn.
+//!
+//! `env_files` are read in order in dotenv format (`KEY=value`, optional
+//! `export`, `#` comments, single‑ or double‑quoted values); `env_vars` then
+//! override them.  In any value, `${file:/path}` is replaced by the file's
+//! contents without the trailing newline and `${env:NAME}` by the daemon's
+//! own variable.
+//!
+//! Files read this way must belong to root or the daemon's user and must be
+//! neither writable by others nor readable by everyone.  Every value taken
+//! from them or from the daemon's environment is a secret: it is redacted
+//! from the daemon's log lines and the captured service output.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::sync::{LazyLock, RwLock};
+
+use nix::unistd::Uid;
+use thiserror::Error;
+
+use crate::config::ServiceDefinition;
+
+/// Stands in for a secret in log output.
+const REDACTED: &str = "[redacted]";
+
+/// Shorter secrets are not redacted – they would mangle unrelated text.
+const MIN_REDACT_LEN: usize = 6;
+
+/// Permission bits a secret file must not have: group write and any
+/// access for others.
+const LOOSE_BITS: u32 = 0o027;
+
+/// Every secret resolved so far, longest first so overlapping ones are
+/// replaced whole.
+static SECRETS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("cannot read '{path}': {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("'{path}' has mode {mode:03o} – must not be group-writable or accessible by others")]
+    Permissions { path: String, mode: u32 },
+
+    #[error("'{path}' belongs to uid {uid}, not root or the daemon's user")]
+    Owner { path: String, uid: u32 },
+
+    #[error("{path}:{line}: expected KEY=value")]
+    Syntax { path: String, line: usize },
+
+    #[error("environment variable '{0}' referenced by ${{env:{0}}} is not set")]
+    MissingEnv(String),
+
+    #[error("unterminated reference in '{0}'")]
+    Unterminated(String),
+}
+
+/// The environment of one run, secrets resolved.
+pub fn resolve(def: &ServiceDefinition) -> Result<HashMap<String, String>, SecretError> {
+    let mut env = HashMap::new();
+    for path in &def.env_files {
+        for (key, value) in parse_env_file(path, &read_secret_file(path)?)? {
+            let value = expand(&value)?;
+            register(&value);
+            env.insert(key, value);
+        }
+    }
+    for (key, value) in &def.env_vars {
+        env.insert(key.clone(), expand(value)?);
+    }
+    Ok(env)
+}
+
+/// `text` with every known secret replaced.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let Ok(secrets) = SECRETS.read() else {
+        return Cow::Borrowed(text);
+    };
+    let mut out = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        if out.contains(secret.as_str()) {
+            out = Cow::Owned(out.replace(secret.as_str(), REDACTED));
+        }
+    }
+    out
+}
+
+fn register(secret: &str) {
+    if secret.len() < MIN_REDACT_LEN {
+        return;
+    }
+    if let Ok(mut secrets) = SECRETS.write()
+        && !secrets.iter().any(|known| known == secret)
+    {
+        secrets.push(secret.to_string());
+        secrets.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// Read a file holding secrets after checking who may change or read it.
+fn read_secret_file(path: &str) -> Result<String, SecretError> {
+    let read_err = |source| SecretError::Read {
+        path: path.to_string(),
+        source,
+    };
+    let meta = fs::metadata(path).map_err(read_err)?;
+    let mode = meta.mode() & 0o7777;
+    if mode & LOOSE_BITS != 0 {
+        return Err(SecretError::Permissions {
+            path: path.to_string(),
+            mode,
+        });
+    }
+    if meta.uid() != 0 && meta.uid() != Uid::effective().as_raw() {
+        return Err(SecretError::Owner {
+            path: path.to_string(),
+            uid: meta.uid(),
+        });
+    }
+    fs::read_to_string(path).map_err(read_err)
+}
+
+/// Replace the `${file:…}` and `${env:…}` references in `raw`; any other
+/// text, `$` included, is kept as written.
+fn expand(raw: &str) -> Result<String, SecretError> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let source = if after.starts_with("file:") {
+            "file:"
+        } else if after.starts_with("env:") {
+            "env:"
+        } else {
+            out.push_str(&rest[..start + 2]);
+            rest = after;
+            continue;
+        };
+        let end = after
+            .find('}')
+            .ok_or_else(|| SecretError::Unterminated(raw.to_string()))?;
+        let target = &after[source.len()..end];
+        let value = if source == "file:" {
+            let content = read_secret_file(target)?;
+            let trimmed = content.strip_suffix('\n').unwrap_or(&content);
+            trimmed.strip_suffix('\r').unwrap_or(trimmed).to_string()
+        } else {
+            std::env::var(target).map_err(|_| SecretError::MissingEnv(target.to_string()))?
+        };
+        register(&value);
+        out.push_str(&rest[..start]);
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parse dotenv `content` read from `path`.
+fn parse_env_file(path: &str, content: &str) -> Result<Vec<(String, String)>, SecretError> {
+    let mut vars = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let syntax = || SecretError::Syntax {
+            path: path.to_string(),
+            line: i + 1,
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or_else(syntax)?;
+        let key = key.trim();
+        let valid_key = key.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err(syntax());
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            quoted.strip_suffix('\'').ok_or_else(syntax)?.to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            unescape(quoted.strip_suffix('"').ok_or_else(syntax)?)
+        } else {
+            value.to_string()
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// Undo the `\n`, `\"` and `\\` escapes of a double‑quoted value.
+fn unescape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => out.push('\n'),
+            ('\\', Some(escaped @ ('"' | '\\'))) => out.push(escaped),
+            _ => {
+                out.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_files_and_redacts() {
+        let vars = parse_env_file(
+            "app.env",
+            "# comment\n\
+             export TOKEN=abc123\n\
+             QUOTED=\"two\\nlines \\\"q\\\"\"\n\
+             RAW='a # b'\n",
+        )
+        .expect("valid file");
+        assert_eq!(
+            vars,
+            [
+                ("TOKEN".to_string(), "abc123".to_string()),
+                ("QUOTED".to_string(), "two\nlines \"q\"".to_string()),
+                ("RAW".to_string(), "a # b".to_string()),
+            ]
+        );
+        assert!(matches!(
+            parse_env_file("app.env", "1BAD=x"),
+            Err(SecretError::Syntax { line: 1, .. })
+        ));
+
+        // SAFETY: the test sets a variable nothing else reads.
+        unsafe { std::env::set_var("KODEGEND_TEST_SECRET", "hunter2-secret") };
+        assert_eq!(
+            expand("Bearer ${env:KODEGEND_TEST_SECRET} ${HOME}").expect("set"),
+            "Bearer hunter2-secret ${HOME}"
+        );
+        assert!(matches!(
+            expand("${env:KODEGEND_TEST_UNSET}"),
+            Err(SecretError::MissingEnv(_))
+        ));
+        assert_eq!(
+            redact("token=hunter2-secret!"),
+            format!("token={REDACTED}!")
+        );
+    }
+}
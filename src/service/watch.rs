@@ -0,0 +1,106 @@
+//! `watch_dirs`: notice file changes under a service's watched directories.
+//!
+//! One inotify instance per service watches every listed directory and its
+//! subdirectories (new ones are picked up as they appear).  A reader thread
+//! turns events into coalesced pings; the worker debounces them and restarts
+//! the service once the tree has been quiet for [`DEBOUNCE`].
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+#[cfg(target_os = "linux")]
+use {
+    crossbeam_channel::{TrySendError, bounded},
+    log::warn,
+    nix::errno::Errno,
+    nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+    std::collections::HashMap,
+    std::fs,
+    std::thread,
+};
+
+/// Quiet period after the last change before the service is restarted.
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Start watching `dirs` for `service`; the receiver gets a ping for every
+/// burst of changes.
+#[cfg(target_os = "linux")]
+pub fn watch(service: &str, dirs: &[PathBuf]) -> io::Result<Receiver<()>> {
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+    let mut paths = HashMap::new();
+    for dir in dirs {
+        add_tree(&inotify, dir.clone(), &mut paths)?;
+    }
+
+    let (tx, rx) = bounded(1);
+    let name = service.to_string();
+    thread::Builder::new()
+        .name(format!("watch-{service}"))
+        .spawn(move || {
+            loop {
+                let events = match inotify.read_events() {
+                    Ok(events) => events,
+                    Err(Errno::EINTR) => continue,
+                    Err(e) => {
+                        warn!("{name}: watch_dirs stopped: {e}");
+                        return;
+                    }
+                };
+                for ev in events {
+                    if !ev
+                        .mask
+                        .contains(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ISDIR)
+                    {
+                        continue;
+                    }
+                    let (Some(parent), Some(child)) = (paths.get(&ev.wd), ev.name) else {
+                        continue;
+                    };
+                    let dir = parent.join(child);
+                    if let Err(e) = add_tree(&inotify, dir.clone(), &mut paths) {
+                        warn!("{name}: cannot watch {}: {e}", dir.display());
+                    }
+                }
+                // A full channel already holds a pending ping.
+                if let Err(TrySendError::Disconnected(())) = tx.try_send(()) {
+                    return;
+                }
+            }
+        })?;
+    Ok(rx)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_service: &str, _dirs: &[PathBuf]) -> io::Result<Receiver<()>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "watch_dirs needs inotify (Linux only)",
+    ))
+}
+
+/// Watch `dir` and every directory below it.
+#[cfg(target_os = "linux")]
+fn add_tree(
+    inotify: &Inotify,
+    dir: PathBuf,
+    paths: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<()> {
+    let mask = AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVE;
+    let mut pending = vec![dir];
+    while let Some(dir) = pending.pop() {
+        let wd = inotify.add_watch(&dir, mask)?;
+        for entry in fs::read_dir(&dir)?.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(entry.path());
+            }
+        }
+        paths.insert(wd, dir);
+    }
+    Ok(())
+}
@@ -19,6 +19,35 @@ pub enum State {
     Failed,
 }
 
+impl State {
+    /// Name used as the `kind` of `Evt::State`.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Stopped => "stopped",
+            Self::Starting => "starting",
+            Self::Running => "running",
+            Self::Stopping => "stopping",
+            Self::Restarting => "restarting",
+            Self::Failed => "failed",
+        }
+    }
+
+    /// Inverse of [`State::as_str`].
+    #[must_use]
+    pub fn from_kind(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "stopped" => Self::Stopped,
+            "starting" => Self::Starting,
+            "running" => Self::Running,
+            "stopping" => Self::Stopping,
+            "restarting" => Self::Restarting,
+            "failed" => Self::Failed,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Event {
     /// External commands
@@ -39,6 +68,9 @@ pub enum Event {
     // Public API - exported in lib.rs, used by transition table for crash recovery
     ProcExit, // unexpected process exit
     #[allow(dead_code)]
+    // Public API - exported in lib.rs, used by transition table for run-to-completion services
+    ProcDone, // a run to completion or socket-activated run exited successfully
+    #[allow(dead_code)]
     // Public API - exported in lib.rs, used by transition table for health checks
     HealthOk,
     #[allow(dead_code)]
@@ -62,29 +94,44 @@ pub enum Action {
 pub struct Transition;
 
 impl Transition {
-    /// Decide the next `(State, Action)` pair.
+    /// Decide the next `(State, Action)` pair; events that make no sense in
+    /// `s` leave it unchanged.
+    #[allow(dead_code)] // Public API - exported in lib.rs, used by external consumers and tests
     #[inline]
     #[must_use]
     pub const fn next(s: State, e: Event) -> (State, Action) {
+        match Self::try_next(s, e) {
+            Some(step) => step,
+            None => (s, Action::Noop),
+        }
+    }
+
+    /// Like [`Transition::next`], but `None` for an impossible transition.
+    #[inline]
+    #[must_use]
+    pub const fn try_next(s: State, e: Event) -> Option<(State, Action)> {
         use Action::{KillProcess, Noop, NotifyHealthy, NotifyUnhealthy, SpawnProcess};
         use Event::{
-            CmdRestart, CmdStart, CmdStop, HealthBad, HealthOk, ProcExit, StartErr, StartedOk,
-            StopDone,
+            CmdRestart, CmdStart, CmdStop, HealthBad, HealthOk, ProcDone, ProcExit, StartErr,
+            StartedOk, StopDone,
         };
         use State::{Failed, Restarting, Running, Starting, Stopped, Stopping};
 
-        match (s, e) {
+        Some(match (s, e) {
             // ── Stopped ────────────────────────────────────────────────────────
             (Stopped, CmdStart) => (Starting, SpawnProcess),
             (Stopped, CmdRestart) => (Starting, SpawnProcess),
-            (Stopped, _) => (Stopped, Noop),
+            (Stopped, CmdStop) => (Stopped, Noop),
 
             // ── Starting ───────────────────────────────────────────────────────
             (Starting, StartedOk) => (Running, NotifyHealthy),
             (Starting, StartErr) => (Failed, NotifyUnhealthy),
             (Starting, CmdStop) => (Stopping, KillProcess),
             (Starting, CmdRestart) => (Restarting, KillProcess),
-            (Starting, _) => (Starting, Noop),
+            (Starting, CmdStart) => (Starting, Noop),
+            // Died before it reported ready.
+            (Starting, ProcExit) => (Failed, NotifyUnhealthy),
+            (Starting, ProcDone) => (Stopped, Noop),
 
             // ── Running ────────────────────────────────────────────────────────
             (Running, HealthBad) => (Failed, NotifyUnhealthy),
@@ -92,28 +139,34 @@ impl Transition {
             (Running, CmdStop) => (Stopping, KillProcess),
             (Running, CmdRestart) => (Restarting, KillProcess),
             (Running, ProcExit) => (Failed, NotifyUnhealthy),
-            (Running, _) => (Running, Noop),
+            (Running, ProcDone) => (Stopped, Noop),
+            (Running, CmdStart) => (Running, Noop),
 
             // ── Stopping ───────────────────────────────────────────────────────
             (Stopping, StopDone) => (Stopped, Noop),
             (Stopping, ProcExit) => (Stopped, Noop),
             (Stopping, CmdStart) => (Stopping, Noop), // ignore while stopping
-            (Stopping, _) => (Stopping, Noop),
+            (Stopping, CmdStop) => (Stopping, Noop),
 
             // ── Restarting ─────────────────────────────────────────────────────
             (Restarting, StopDone) => (Starting, SpawnProcess),
             (Restarting, ProcExit) => (Starting, SpawnProcess),
             (Restarting, StartedOk) => (Running, NotifyHealthy),
             (Restarting, StartErr) => (Failed, NotifyUnhealthy),
-            (Restarting, _) => (Restarting, Noop),
+            (Restarting, CmdStop) => (Stopping, KillProcess),
 
             // ── Failed ─────────────────────────────────────────────────────────
-            (Failed, CmdStart) => (Starting, SpawnProcess),
-            (Failed, CmdRestart) => (Starting, SpawnProcess),
-            (Failed, CmdStop) => (Stopped, Noop),
+            // An unhealthy process may still be alive: clean it up first.
+            (Failed, CmdStart) => (Restarting, KillProcess),
+            (Failed, CmdRestart) => (Restarting, KillProcess),
+            (Failed, CmdStop) => (Stopping, KillProcess),
             (Failed, HealthOk) => (Running, NotifyHealthy),
-            (Failed, _) => (Failed, Noop),
-        }
+            (Failed, HealthBad) => (Failed, Noop),
+            (Failed, ProcExit) => (Failed, Noop),
+            (Failed, ProcDone) => (Failed, Noop),
+
+            _ => return None,
+        })
     }
 }
 
@@ -135,4 +188,27 @@ mod tests {
         let (s4, a4) = Transition::next(s3, Event::StopDone);
         assert_eq!((s4, a4), (State::Stopped, Action::Noop));
     }
+
+    #[test]
+    fn failed_service_is_cleaned_up_before_respawn() {
+        let (s1, a1) = Transition::next(State::Running, Event::ProcExit);
+        assert_eq!((s1, a1), (State::Failed, Action::NotifyUnhealthy));
+
+        let (s2, a2) = Transition::next(s1, Event::CmdStart);
+        assert_eq!((s2, a2), (State::Restarting, Action::KillProcess));
+
+        let (s3, a3) = Transition::next(s2, Event::StopDone);
+        assert_eq!((s3, a3), (State::Starting, Action::SpawnProcess));
+
+        assert_eq!(Transition::try_next(State::Stopped, Event::StopDone), None);
+    }
+
+    #[test]
+    fn completed_run_stops_without_failing() {
+        let (s1, a1) = Transition::next(State::Running, Event::ProcDone);
+        assert_eq!((s1, a1), (State::Stopped, Action::Noop));
+
+        let (s2, a2) = Transition::next(s1, Event::CmdStart);
+        assert_eq!((s2, a2), (State::Starting, Action::SpawnProcess));
+    }
 }